@@ -38,9 +38,17 @@ fn main() {
                     }
                 }
             },
-            Event::Update => {
+            Event::Mouse(mouse) => {
+                if mouse.kind == MouseEventKind::Down(MouseButton::Left) {
+                    direction *= -1.0;
+                }
+            },
+            Event::Update(input) => {
+                // Holding Up speeds the rotation up
+                let speed = if input.is_pressed(KeyCode::Up) { 0.02 } else { 0.005 };
+
                 transform.rotation *= glm::quat_angle_axis(
-                    0.005 * direction, 
+                    speed * direction, 
                     &glm::Vec3::z()
                 );
             },
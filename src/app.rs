@@ -1,11 +1,12 @@
-use std::{sync::{atomic::{AtomicBool, Ordering}, OnceLock}, time::Duration};
+use std::{cell::RefCell, collections::VecDeque, io::{Stdout, Write}, sync::{atomic::{AtomicBool, Ordering}, mpsc, Mutex, MutexGuard, OnceLock, PoisonError}, thread::{Scope, ScopedJoinHandle}, time::{Duration, Instant}};
 
 use game_loop::game_loop;
+use image::Rgb;
 
-use crate::{event::Event, fatal, prelude::Buffer, render::Renderer, terminal::Terminal, utils::Size};
+use crate::{event::{input::{Input, KeyboardInput}, state::{InputState, DEFAULT_REPEAT_TIMEOUT}, Event}, fatal, render::{capture::FrameCapture, cells::CellPass, downsample::DownsampleFilter, padded_bytes_per_row, readback::{ReadbackRing, DEFAULT_STAGING_BUFFERS}, Renderer}, terminal::{capabilities::{TerminalCapabilities, DEFAULT_PROBE_TIMEOUT}, cell::CellGrid, color::{ColorDepth, Dithering}, encoder::OutputMode, InputArea, ScreenMode, Terminal}, utils::{Size, Viewport}};
 
 pub mod prelude {
-    pub use super::TermApp;
+    pub use super::{ScriptedInput, TermApp, TermAppDescriptor};
     pub use super::{exit, should_exit};
 }
 
@@ -19,54 +20,285 @@ pub fn should_exit() -> bool {
     EXIT.get_or_init(|| AtomicBool::new(false)).load(Ordering::SeqCst)
 }
 
-pub struct TermApp {
+/// Resets the exit flag, so the next run doesn't stop immediately
+fn reset_exit() {
+    EXIT.get_or_init(|| AtomicBool::new(false)).store(false, Ordering::SeqCst);
+}
+
+/// Keyboard input, delivered to the handler of a headless run
+/// before the update of the given frame
+pub struct ScriptedInput {
+    /// Number of the frame, counting from 0
+    pub frame: u64,
+    pub input: KeyboardInput,
+}
+
+/// Describes how [`TermApp`] is set up
+#[derive(Debug, Clone)]
+pub struct TermAppDescriptor {
+    /// Delay after each rendered frame
+    pub timeout: Duration,
+    /// Output mode, used to draw rendered frames in the terminal.
+    /// If `None`, the best mode supported by the terminal is used
+    pub output_mode: Option<OutputMode>,
+    /// Number of colors, used by text-based output modes.
+    /// If `None`, it is detected from the environment
+    pub color_depth: Option<ColorDepth>,
+    /// Dithering, applied when color depth is lower than truecolor
+    pub dithering: Dithering,
+    /// Time to wait for replies, when terminal capabilities and theme colors are queried
+    pub probe_timeout: Duration,
+    /// Where frames are drawn: in the alternate screen or inline, below
+    /// the cursor. Headless applications ignore it
+    pub screen_mode: ScreenMode,
+    /// Encode cells on the GPU with [`CellPass`] in the output modes,
+    /// which support it, so only cells are read back
+    pub gpu_cells: bool,
+    /// Number of canvas pixels per output pixel along each axis. Frames
+    /// are rendered at this multiple of the output size, which smooths
+    /// edges, and reduced with `downsample_filter`
+    pub supersampling: u32,
+    pub downsample_filter: DownsampleFilter,
+    /// Time after the last press or repeat, in which [`InputState`] considers
+    /// a key held, until the terminal reports a release
+    pub repeat_timeout: Duration,
+    /// Clear the canvas to a transparent color, so the terminal background
+    /// shows through the pixels, which are not drawn
+    pub transparent_background: bool,
+}
+
+impl Default for TermAppDescriptor {
+    fn default() -> Self {
+        TermAppDescriptor {
+            timeout: Duration::from_millis(20),
+            output_mode: None,
+            color_depth: None,
+            dithering: Dithering::default(),
+            probe_timeout: DEFAULT_PROBE_TIMEOUT,
+            screen_mode: ScreenMode::default(),
+            gpu_cells: true,
+            supersampling: 1,
+            downsample_filter: DownsampleFilter::default(),
+            repeat_timeout: DEFAULT_REPEAT_TIMEOUT,
+            transparent_background: false,
+        }
+    }
+}
+
+/// Frame, which is submitted for readback, but not printed yet
+enum PendingFrame {
+    Canvas { number: u64, width: u32, height: u32 },
+    Cells,
+}
+
+/// Frame, read back from the GPU and ready to be printed
+enum FrameOutput {
+    Image { data: Vec<u8>, width: u32, height: u32 },
+    Cells(CellGrid),
+}
+
+impl FrameOutput {
+    fn print<W: Write>(self, terminal: &mut Terminal<W>) {
+        match self {
+            FrameOutput::Image { data, width, height } => terminal.print_image(&data, width, height),
+            FrameOutput::Cells(grid) => terminal.print_cells(&grid),
+        }
+    }
+}
+
+enum WriterCommand {
+    Print(FrameOutput),
+    /// Replies, when all frames before it are printed
+    Sync(mpsc::Sender<()>),
+}
+
+/// Terminal, shared by the application and the frame writer during a run
+type SharedTerminal<'t, W> = Mutex<&'t mut Terminal<W>>;
+
+/// Thread, which prints frames to the terminal, so terminal I/O
+/// of a frame overlaps rendering of the next one
+struct FrameWriter<'scope> {
+    sender: mpsc::SyncSender<WriterCommand>,
+    thread: ScopedJoinHandle<'scope, ()>,
+}
+
+impl<'scope> FrameWriter<'scope> {
+    fn spawn<W: Write + Send>(scope: &'scope Scope<'scope, '_>, terminal: &'scope SharedTerminal<'_, W>) -> FrameWriter<'scope> {
+        // Sending blocks, until the previous frame is printed, so frames don't queue up
+        let (sender, receiver) = mpsc::sync_channel(0);
+
+        let thread = std::thread::Builder::new()
+            .name("termgpu-frame-writer".to_string())
+            .spawn_scoped(scope, move || {
+                for command in receiver {
+                    match command {
+                        WriterCommand::Print(output) => output.print(&mut lock(terminal)),
+                        WriterCommand::Sync(reply) => { let _ = reply.send(()); },
+                    }
+                }
+            })
+            .unwrap_or_else(|e| fatal!("Failed to spawn frame writer: {e}"));
+
+        FrameWriter { sender, thread }
+    }
+
+    fn print(&self, output: FrameOutput) {
+        if self.sender.send(WriterCommand::Print(output)).is_err() {
+            log::error!("Frame writer has stopped");
+        }
+    }
+
+    /// Waits until all sent frames are printed
+    fn sync(&self) {
+        let (reply, done) = mpsc::channel();
+
+        if self.sender.send(WriterCommand::Sync(reply)).is_ok() {
+            let _ = done.recv();
+        }
+    }
+
+    fn join(self) {
+        drop(self.sender);
+
+        if self.thread.join().is_err() {
+            log::error!("Frame writer has panicked");
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Application, which renders frames with the GPU and draws them
+/// into the terminal sink. By default the sink is the standard output.
+///
+/// Frames are read back asynchronously: a frame is printed, while the
+/// next one renders, which adds a single frame of latency. Printing runs
+/// on a separate thread during [`TermApp::run`] and [`TermApp::run_until`],
+/// so the sink must be [`Send`]
+pub struct TermApp<W: Write = Stdout> {
+    terminal: Terminal<W>,
+    state: AppState,
+}
+
+/// Everything of the application but the terminal, which
+/// is shared with the frame writer during a run
+struct AppState {
     renderer: Renderer,
-    terminal: Terminal,
-    intermediate_buffer: Buffer<u8>,
+    readback: ReadbackRing,
     timeout: Duration,
+    frame: u64,
+    captures: Vec<FrameCapture>,
+    gpu_cells: bool,
+    cell_pass: Option<CellPass>,
+    output_mode: OutputMode,
+    pending: VecDeque<PendingFrame>,
+    input_state: InputState,
+    /// Whether the renderer has been resized since the last update
+    resized: bool,
 }
 
 impl TermApp {
     pub fn new(timeout: Duration) -> TermApp {
+        TermApp::new_with_descriptor(TermAppDescriptor {
+            timeout,
+            ..Default::default()
+        })
+    }
+
+    pub fn new_with_descriptor(descriptor: TermAppDescriptor) -> TermApp {
         let (cols, rows) = crossterm::terminal::size()
             .unwrap_or_else(|e| fatal!("Failed to get terminal size: {e}"));
 
-        let renderer = Renderer::new(Size::new_terminal(cols, rows))
-            .unwrap_or_else(|e| fatal!("Failed to initialize renderer: {e}"));
+        let mut terminal = Terminal::new(cols, rows);
+        let capabilities = terminal.probe_capabilities(descriptor.probe_timeout).clone();
 
-        let Size::Renderer(width, height) = renderer.size().to_renderer() else { unreachable!() };
-        let unpadded_bytes_per_row = width * 4;
-        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT; // 256
-        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        terminal.set_output_mode(descriptor.output_mode.clone().unwrap_or_else(|| capabilities.output_mode()));
+        terminal.set_color_depth(descriptor.color_depth.unwrap_or(capabilities.color_depth));
+        terminal.set_dithering(descriptor.dithering);
 
-        let intermediate_buffer = Buffer::new(
-                &renderer, 
-                (padded_bytes_per_row as usize) * (height as usize), 
-                wgpu::BufferUsages::COPY_DST
-                    | wgpu::BufferUsages::MAP_READ,
-            );
+        if let ScreenMode::Inline(inline_rows) = descriptor.screen_mode {
+            terminal.set_screen_mode(ScreenMode::Inline(inline_rows.min(rows)));
+        }
 
-        TermApp {
-            renderer,
-            intermediate_buffer,
-            terminal: Terminal::new(cols, rows),
-            timeout,
+        TermApp::with_terminal(terminal, &descriptor)
+            .unwrap_or_else(|e| fatal!("Failed to initialize renderer: {e}"))
+    }
+}
+
+impl<W: Write> TermApp<W> {
+    /// Creates an application, which doesn't need a TTY: raw mode is never
+    /// enabled, and the terminal is not queried for its size or capabilities.
+    /// Frames of the fixed `size` are written to the `sink`. Capabilities are
+    /// detected from the environment only, and `probe_timeout` is ignored
+    pub fn new_headless(size: Size, sink: W, descriptor: TermAppDescriptor) -> anyhow::Result<TermApp<W>> {
+        let capabilities = TerminalCapabilities::from_env();
+        let output_mode = descriptor.output_mode.clone().unwrap_or_else(|| capabilities.output_mode());
+
+        // Output mode is set before the size conversion, which uses its cell resolution
+        let mut terminal = Terminal::new_with_sink(0, 0, sink, output_mode);
+        terminal.resize_with(size);
+        terminal.set_color_depth(descriptor.color_depth.unwrap_or(capabilities.color_depth));
+        terminal.set_dithering(descriptor.dithering);
+
+        TermApp::with_terminal(terminal, &descriptor)
+    }
+
+    fn with_terminal(terminal: Terminal<W>, descriptor: &TermAppDescriptor) -> anyhow::Result<TermApp<W>> {
+        let mut renderer = Renderer::new(terminal.size().to_renderer(terminal.cell_resolution()))?;
+        renderer.set_cell_resolution(terminal.cell_resolution());
+        renderer.set_cell_pixels(terminal.cell_pixels());
+        renderer.set_supersampling(descriptor.supersampling, descriptor.downsample_filter);
+
+        // The canvas is transparent, but pixels at the edges
+        // of shapes blend with the background of the theme
+        if descriptor.transparent_background {
+            let Rgb([r, g, b]) = terminal.theme().background.unwrap_or(Rgb([0, 0, 0]));
+
+            renderer.set_clear_color(wgpu::Color {
+                r: r as f64 / 255.0,
+                g: g as f64 / 255.0,
+                b: b as f64 / 255.0,
+                a: 0.0,
+            });
         }
+
+        let Size::Renderer(width, height) = renderer.size() else { unreachable!() };
+
+        let readback = ReadbackRing::new(&renderer, DEFAULT_STAGING_BUFFERS, readback_buffer_len(width, height));
+
+        Ok(TermApp {
+            state: AppState {
+                renderer,
+                readback,
+                output_mode: terminal.output_mode().clone(),
+                timeout: descriptor.timeout,
+                frame: 0,
+                captures: vec![],
+                gpu_cells: descriptor.gpu_cells,
+                cell_pass: None,
+                pending: VecDeque::new(),
+                input_state: InputState::new(descriptor.repeat_timeout),
+                resized: false,
+            },
+            terminal,
+        })
     }
 
     pub fn renderer(&self) -> &Renderer {
-        &self.renderer
+        &self.state.renderer
     }
 
     pub fn renderer_mut(&mut self) -> &mut Renderer {
-        &mut self.renderer
+        &mut self.state.renderer
     }
 
-    pub fn terminal(&self) -> &Terminal {
+    pub fn terminal(&self) -> &Terminal<W> {
         &self.terminal
     }
 
-    pub fn terminal_mut(&mut self) -> &mut Terminal {
+    pub fn terminal_mut(&mut self) -> &mut Terminal<W> {
         &mut self.terminal
     }
 
@@ -74,75 +306,411 @@ impl TermApp {
         self.renderer().size()
     }
 
+    /// Held keys and the mouse, as of the last update
+    pub fn input_state(&self) -> &InputState {
+        &self.state.input_state
+    }
+
+    /// Number of frames, rendered so far
+    pub fn frame(&self) -> u64 {
+        self.state.frame
+    }
+
+    /// Adds a capture of rendered frames. Frames are numbered from 0, and
+    /// captures, which are not complete when the run stops, are saved
+    /// with the frames captured so far. A capture, whose frames have
+    /// already been rendered, is dropped with a warning
+    pub fn capture(&mut self, capture: FrameCapture) {
+        if capture.frames().end <= self.state.frame {
+            log::warn!(
+                "Capture `{}` of frames {:?} is dropped: {} frames have already been rendered",
+                capture.path().display(),
+                capture.frames(),
+                self.state.frame,
+            );
+            return;
+        }
+
+        self.state.captures.push(capture);
+    }
+
     pub fn run<F>(mut self, handler: F)
     where
         F: FnMut(Event),
+        W: Send,
     {
-        use std::cell::RefCell;
         let handler = RefCell::new(handler);
 
+        reset_exit();
+
         // Enable terminal
         self.terminal.enable();
+        self.state.output_mode = self.terminal.output_mode().clone();
+
+        let terminal = Mutex::new(&mut self.terminal);
+
+        std::thread::scope(|scope| {
+            let mut session = Session::start(scope, &mut self.state, &terminal);
+
+            game_loop(
+                &mut session, 240, 0.1, 
+                |update_game| {
+                    if should_exit() {
+                        update_game.exit();
+                    }
+
+                    // 1. Input. All pending events are handled, so fast mouse motion doesn't build
+                    // a backlog. Events are mapped without the terminal, so reading them doesn't
+                    // wait for the frame writer
+                    while crossterm::event::poll(Duration::ZERO).unwrap_or(false) {
+                        let Ok(event) = crossterm::event::read() else { break };
+                        let input = update_game.game.input(event);
+
+                        let state = &mut *update_game.game.state;
+                        match input {
+                            Some(Input::Keyboard(input)) => {
+                                state.input_state.handle_key(&input, Instant::now());
+                                handler.borrow_mut()(Event::Input(input));
+                            },
+                            Some(Input::Mouse(input)) => {
+                                state.input_state.handle_mouse(&input);
+                                handler.borrow_mut()(Event::Mouse(input));
+                            },
+                            None => {},
+                        }
+                    }
+
+                    // 2. Check resize
+                    update_game.game.check_resize();
+
+                    if std::mem::take(&mut update_game.game.state.resized) {
+                        handler.borrow_mut()(Event::Resize(update_game.game.state.renderer.size()));
+                    }
+
+                    // 3. Update
+                    let state = &mut *update_game.game.state;
+                    state.input_state.expire(Instant::now());
+                    handler.borrow_mut()(Event::Update(&state.input_state));
+                    state.input_state.end_update();
+                },
+                |render_game| {
+                    render_game.game.render_frame(&mut *handler.borrow_mut());
+
+                    std::thread::sleep(render_game.game.state.timeout);
+                },
+            );
 
-        game_loop(
-            &mut self, 240, 0.1, 
-            |update_game| {
-                if should_exit() {
-                    update_game.exit();
-                }
+            session.stop();
+        });
+
+        self.terminal.disable();
+        self.state.save_captures(None);
+    }
+
+    /// Runs `frames` frames without a TTY and returns the sink. See [`TermApp::run_until`]
+    pub fn run_frames<F>(self, frames: u64, script: impl IntoIterator<Item = ScriptedInput>, handler: F) -> W
+    where
+        F: FnMut(Event),
+        W: Send,
+    {
+        self.run_until(script, |frame| frame >= frames, handler)
+    }
+
+    /// Runs frames without a TTY, until `condition` returns `true` for the number
+    /// of rendered frames or [`exit`] is called, and returns the sink. Every frame
+    /// gets scripted input, a single update and a render, without any delay,
+    /// so the output doesn't depend on timing. [`InputState`] counts time in
+    /// frames of the descriptor `timeout` instead of the wall clock
+    pub fn run_until<C, F>(mut self, script: impl IntoIterator<Item = ScriptedInput>, mut condition: C, mut handler: F) -> W
+    where
+        C: FnMut(u64) -> bool,
+        F: FnMut(Event),
+        W: Send,
+    {
+        let mut script = script.into_iter().collect::<Vec<_>>();
+        script.sort_by_key(|scripted| std::cmp::Reverse(scripted.frame));
+
+        reset_exit();
+        self.state.output_mode = self.terminal.output_mode().clone();
+
+        let terminal = Mutex::new(&mut self.terminal);
+
+        std::thread::scope(|scope| {
+            let mut session = Session::start(scope, &mut self.state, &terminal);
+            let start = Instant::now();
+
+            let mut frame = 0;
+            while !should_exit() && !condition(frame) {
+                let now = start + session.state.timeout * frame as u32;
 
                 // 1. Input
-                if let Some(input) = update_game.game.terminal.input(Duration::from_millis(10)) {
-                    handler.borrow_mut()(Event::Input(input));
+                while script.last().is_some_and(|scripted| scripted.frame <= frame) {
+                    let scripted = script.pop().unwrap();
+                    session.terminal().record_input(&scripted.input);
+                    session.state.input_state.handle_key(&scripted.input, now);
+
+                    handler(Event::Input(scripted.input));
                 }
 
-                // 2. Check resize
-                if let Some(size) = update_game.game.terminal.resized() {
-                    let size = size.to_renderer();
-                    let Size::Renderer(width, height) = size else { unreachable!() };
-
-                    let unpadded_bytes_per_row = width * 4;
-                    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT; // 256
-                    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
-
-                    update_game.game.renderer.resize_with(size);
-                    update_game.game.intermediate_buffer.resize(
-                        &update_game.game.renderer,
-                        (padded_bytes_per_row as usize) * (height as usize),
-                    );
-                    handler.borrow_mut()(Event::Resize(size));
+                // 2. Report a resize of the viewport
+                if std::mem::take(&mut session.state.resized) {
+                    handler(Event::Resize(session.state.renderer.size()));
                 }
 
                 // 3. Update
-                handler.borrow_mut()(Event::Update);
-            },
-            |render_game| {
-                let renderer = &mut render_game.game.renderer;
+                let input_state = &mut session.state.input_state;
+                input_state.expire(now);
+                handler(Event::Update(input_state));
+                input_state.end_update();
 
                 // 4. Render
-                handler.borrow_mut()(Event::Render(renderer));
+                session.render_frame(&mut handler);
+                frame += 1;
+            }
 
-                // 4.1. Draw rendered texture to terminal
-                // 4.1.1. Copy texture to intermediate buffer
-                let mut draw_ctx = renderer.draw_ctx();
-                let canvas = renderer.canvas();
-                let width = canvas.texture().descriptor().width;
-                let height = canvas.texture().descriptor().height;
+            session.stop();
+        });
 
-                draw_ctx.copy_texture_to_buffer(
-                    canvas.texture(),
-                    &render_game.game.intermediate_buffer,
-                );
+        self.state.save_captures(None);
+        self.terminal.into_sink()
+    }
+
+    /// Limits drawing to the rectangle of cells, or restores drawing to
+    /// the whole screen if `None`. The renderer is resized to the viewport,
+    /// and the handler gets [`Event::Resize`] on the first update of the run
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.terminal.set_viewport(viewport);
+        self.state.resize_renderer(&self.terminal);
+    }
+}
+
+impl AppState {
+    /// Resizes the renderer to the terminal, taking its cell size with it.
+    /// The handler gets [`Event::Resize`] on the next update
+    fn resize_renderer<W: Write>(&mut self, terminal: &Terminal<W>) {
+        self.resized = true;
+
+        self.renderer.set_cell_resolution(terminal.cell_resolution());
+        self.renderer.set_cell_pixels(terminal.cell_pixels());
+        self.renderer.resize_with(terminal.size());
+
+        let Size::Renderer(width, height) = self.renderer.size() else { unreachable!() };
+        self.readback.resize(&self.renderer, readback_buffer_len(width, height));
+    }
+
+    /// Saves captures, which are complete after the frame, or all of them, if `None`
+    fn save_captures(&mut self, frame: Option<u64>) {
+        let (complete, pending) = std::mem::take(&mut self.captures)
+            .into_iter()
+            .partition::<Vec<_>, _>(|capture| frame.is_none_or(|frame| capture.is_complete(frame)));
+
+        self.captures = pending;
+
+        for capture in complete {
+            let path = capture.path().to_path_buf();
+
+            if let Err(e) = capture.save() {
+                log::error!("Failed to save capture `{}`: {e}", path.display());
+            }
+        }
+    }
+}
+
+/// Application during a run. Frames are printed by the frame
+/// writer, which shares the terminal with the application
+struct Session<'s, 't, W: Write> {
+    state: &'s mut AppState,
+    terminal: &'s SharedTerminal<'t, W>,
+    writer: Option<FrameWriter<'s>>,
+    /// Screen size of the TTY, which has been last checked for a resize
+    screen: Option<(u16, u16)>,
+    /// Cached drawn area of the terminal, which input is mapped to
+    input_area: InputArea,
+    recording: bool,
+}
+
+impl<'s, 't, W: Write + Send> Session<'s, 't, W> {
+    /// Starts printing frames on the frame writer thread
+    fn start(scope: &'s Scope<'s, '_>, state: &'s mut AppState, terminal: &'s SharedTerminal<'t, W>) -> Session<'s, 't, W> {
+        let (input_area, recording) = {
+            let terminal = lock(terminal);
+            (terminal.input_area(), terminal.is_recording())
+        };
+
+        Session {
+            state,
+            terminal,
+            writer: Some(FrameWriter::spawn(scope, terminal)),
+            screen: None,
+            input_area,
+            recording,
+        }
+    }
+}
+
+impl<'s, 't, W: Write> Session<'s, 't, W> {
+    fn terminal(&self) -> MutexGuard<'s, &'t mut Terminal<W>> {
+        lock(self.terminal)
+    }
+
+    /// Prints pending frames and stops the frame writer
+    fn stop(mut self) {
+        self.flush_frames();
+
+        if let Some(writer) = self.writer.take() {
+            writer.join();
+        }
+    }
+
+    /// Maps a terminal event to input. The terminal is locked only
+    /// to record keys, so input doesn't wait for the frame writer
+    fn input(&mut self, event: crossterm::event::Event) -> Option<Input> {
+        match event {
+            crossterm::event::Event::Key(event) => {
+                let input = KeyboardInput::from(event);
+
+                if self.recording {
+                    self.terminal().record_input(&input);
+                }
+
+                Some(Input::Keyboard(input))
+            },
+            crossterm::event::Event::Mouse(event) => Some(Input::Mouse(self.input_area.mouse_input(event))),
+            _ => None,
+        }
+    }
+
+    /// Resizes the terminal, if the TTY has been resized. The terminal
+    /// is locked only, when the size differs from the last checked one
+    fn check_resize(&mut self) {
+        let screen = crossterm::terminal::size().ok();
+        if screen.is_none() || screen == self.screen {
+            return;
+        }
+
+        self.screen = screen;
+
+        let resized = self.terminal().resized();
+        if let Some(size) = resized {
+            self.resize_with(size);
+        }
+    }
+
+    /// Resizes the screen. The renderer follows the size of the viewport, if there is one
+    fn resize_with(&mut self, size: Size) {
+        self.flush_frames();
+
+        let mut terminal = lock(self.terminal);
+        terminal.resize_with(size);
+        self.state.resize_renderer(&terminal);
+        self.input_area = terminal.input_area();
+    }
 
-                draw_ctx.apply(canvas, renderer);
+    /// Renders a frame and prints the previous one to the terminal
+    fn render_frame(&mut self, handler: &mut impl FnMut(Event)) {
+        // 4. Render
+        handler(Event::Render(&mut self.state.renderer));
 
-                let data = pollster::block_on(render_game.game.intermediate_buffer.read_bytes(renderer));
-                render_game.game.intermediate_buffer.unmap();
+        // 4.1. Start reading the rendered texture back. Captured frames
+        // are read back in full, other ones may be encoded on the GPU
+        let frame = self.state.frame;
+        let captured = self.state.captures.iter().any(|capture| capture.wants(frame));
 
-                // 4.1.2. Save the texture to a vec of bytes
+        if captured || !self.submit_cells() {
+            self.state.submit_canvas();
+        }
+
+        self.state.frame += 1;
+
+        // 4.2. Print the previous frame, while this one is being read back
+        while self.state.pending.len() > 1 {
+            self.print_oldest();
+        }
+
+        // 5. Draw UI
+        // TODO: Implement UI context
+    }
+
+    /// Encodes the canvas into cells with the [`CellPass`]. Returns `false`,
+    /// if it is disabled or not supported by the output mode
+    fn submit_cells(&mut self) -> bool {
+        if !self.state.gpu_cells {
+            return false;
+        }
+
+        let state = &*self.state;
+        if !state.cell_pass.as_ref().is_some_and(|pass| pass.is_compatible(&state.renderer, &state.output_mode)) {
+            // Cells, pending in the old pass, are printed first
+            self.flush_frames();
+            self.state.cell_pass = CellPass::new(&self.state.renderer, &self.state.output_mode);
+        }
+
+        let state = &mut *self.state;
+        let Some(pass) = &mut state.cell_pass else { return false };
+        pass.submit(&state.renderer);
+        state.pending.push_back(PendingFrame::Cells);
+
+        true
+    }
+
+    /// Prints all pending frames and waits, until they are written
+    fn flush_frames(&mut self) {
+        while !self.state.pending.is_empty() {
+            self.print_oldest();
+        }
+
+        if let Some(writer) = &self.writer {
+            writer.sync();
+        }
+    }
+
+    /// Reads the oldest pending frame back and passes it to the frame writer
+    fn print_oldest(&mut self) {
+        let Some(output) = self.state.read_oldest() else { return };
+
+        match &self.writer {
+            Some(writer) => writer.print(output),
+            None => output.print(&mut lock(self.terminal)),
+        }
+    }
+}
+
+impl AppState {
+    /// Copies the whole output texture to a staging buffer
+    fn submit_canvas(&mut self) {
+        let renderer = &self.renderer;
+
+        let mut draw_ctx = renderer.draw_ctx();
+        renderer.downsample(&mut draw_ctx);
+
+        let output = renderer.output();
+        let width = output.texture().descriptor().width;
+        let height = output.texture().descriptor().height;
+
+        draw_ctx.copy_texture_to_buffer(output.texture(), self.readback.buffer());
+
+        let submission = draw_ctx.apply(output, renderer);
+        self.readback.submit(submission);
+
+        self.pending.push_back(PendingFrame::Canvas { number: self.frame, width, height });
+    }
+
+    /// Reads the oldest pending frame back. Full canvas
+    /// frames are passed to captures as well
+    fn read_oldest(&mut self) -> Option<FrameOutput> {
+        match self.pending.pop_front()? {
+            PendingFrame::Cells => {
+                let mut grid = CellGrid::default();
+                let pass = self.cell_pass.as_mut()?;
+
+                pass.read_oldest(&self.renderer, &mut grid).then_some(FrameOutput::Cells(grid))
+            },
+            PendingFrame::Canvas { number, width, height } => {
+                let data = self.readback.read_oldest(&self.renderer)?;
+
+                // Remove the row padding of the copy
                 let unpadded_bytes_per_row = width * 4;
-                let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT; // 256
-                let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+                let padded_bytes_per_row = padded_bytes_per_row(width);
 
                 let mut raw_data = Vec::with_capacity((width * height * 4) as usize);
                 for row in 0..height {
@@ -151,16 +719,22 @@ impl TermApp {
                     raw_data.extend_from_slice(&data[start..end]);
                 }
 
-                // 4.1.3. Print the image to the terminal
-                render_game.game.terminal.print_image(&raw_data, width, height);
+                if self.captures.iter().any(|capture| capture.wants(number)) {
+                    let image = image::RgbaImage::from_raw(width, height, raw_data.clone()).unwrap();
 
-                // 5. Draw UI
-                // TODO: Implement UI context
+                    for capture in self.captures.iter_mut().filter(|capture| capture.wants(number)) {
+                        capture.push(image.clone());
+                    }
 
-                std::thread::sleep(render_game.game.timeout);
-            },
-        );
+                    self.save_captures(Some(number));
+                }
 
-        self.terminal.disable();
+                Some(FrameOutput::Image { data: raw_data, width, height })
+            },
+        }
     }
-}
\ No newline at end of file
+}
+
+fn readback_buffer_len(width: u32, height: u32) -> usize {
+    padded_bytes_per_row(width) as usize * height as usize
+}
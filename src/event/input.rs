@@ -1,6 +1,57 @@
-use crossterm::event::{KeyCode, KeyEventKind};
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers, MouseEventKind};
 
 pub struct KeyboardInput {
     pub code: KeyCode,
+    /// Press, repeat or release. Terminals without the keyboard
+    /// enhancement protocol report presses only
     pub kind: KeyEventKind,
-}
\ No newline at end of file
+    /// Modifier keys, held with the key
+    pub modifiers: KeyModifiers,
+    /// Caps Lock, Num Lock and keypad state. It is reported only
+    /// with the keyboard enhancement protocol
+    pub state: KeyEventState,
+}
+
+impl KeyboardInput {
+    /// Creates input of the key without modifiers
+    pub fn new(code: KeyCode, kind: KeyEventKind) -> KeyboardInput {
+        KeyboardInput {
+            code,
+            kind,
+            modifiers: KeyModifiers::NONE,
+            state: KeyEventState::NONE,
+        }
+    }
+}
+
+impl From<KeyEvent> for KeyboardInput {
+    fn from(event: KeyEvent) -> Self {
+        KeyboardInput {
+            code: event.code,
+            kind: event.kind,
+            modifiers: event.modifiers,
+            state: event.state,
+        }
+    }
+}
+
+/// Mouse button press, release, drag, move or scroll
+pub struct MouseInput {
+    /// What happened. Presses, releases and drags carry the button
+    pub kind: MouseEventKind,
+    /// Modifier keys, held during the event
+    pub modifiers: KeyModifiers,
+    /// Column and row of the cell under the pointer, relative to the top
+    /// left corner of the drawn area. They are negative or beyond its size,
+    /// if the pointer is outside of the area
+    pub cell: (i32, i32),
+    /// Position in renderer pixels: the center of the cell under the
+    /// pointer, mapped through the cell resolution of the output mode
+    pub pixel: (f32, f32),
+}
+
+/// Input, read from the terminal
+pub enum Input {
+    Keyboard(KeyboardInput),
+    Mouse(MouseInput),
+}
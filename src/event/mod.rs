@@ -1,24 +1,32 @@
 pub mod input;
+pub mod state;
 
 use crate::{render::Renderer, ui::UiContext, utils::Size};
 
-use input::KeyboardInput;
+use input::{KeyboardInput, MouseInput};
+use state::InputState;
 
 pub mod prelude {
     pub use super::{
         Event,
         KeyCode,
         KeyEventKind,
+        KeyEventState,
+        KeyModifiers,
+        MouseButton,
+        MouseEventKind,
     };
     pub use super::input::*;
+    pub use super::state::*;
 }
 
-pub use crossterm::event::{KeyCode, KeyEventKind};
+pub use crossterm::event::{KeyCode, KeyEventKind, KeyEventState, KeyModifiers, MouseButton, MouseEventKind};
 
 pub enum Event<'a> {
     Input(KeyboardInput),       // 1.
+    Mouse(MouseInput),          // 1.
     Resize(Size),               // 2.
-    Update,                     // 3.
+    Update(&'a InputState),     // 3.
     Render(&'a mut Renderer),   // 4.
     DrawUi(&'a mut UiContext),  // 5.
 }
\ No newline at end of file
@@ -0,0 +1,299 @@
+//! State module tracks held keys and the mouse between updates,
+//! so handlers don't have to follow every input event themselves
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use crossterm::event::{KeyCode, KeyEventKind, MouseButton, MouseEventKind};
+
+use super::input::{KeyboardInput, MouseInput};
+
+/// Time after the last press or repeat, in which a key is considered held,
+/// if the terminal doesn't report its releases. It must be longer than the
+/// delay before the key starts repeating, which is 660 ms by default in X11
+/// (see `xset q`), 500 to 600 ms in Wayland compositors and 500 ms in Windows
+pub const DEFAULT_REPEAT_TIMEOUT: Duration = Duration::from_millis(750);
+
+/// Keys and mouse buttons, held at the moment of the update, and changes
+/// since the previous update. [`TermApp`](crate::app::TermApp) passes it
+/// with [`Event::Update`](super::Event::Update).
+///
+/// Most terminals report only presses and their repeats. Until a release
+/// of a key is reported, the key is released, when neither a press nor
+/// a repeat has come for the repeat timeout. Terminals may report releases
+/// of some keys only, so it is tracked for every key separately
+#[derive(Debug, Clone)]
+pub struct InputState {
+    pressed: HashMap<KeyCode, Instant>,
+    just_pressed: HashSet<KeyCode>,
+    just_released: HashSet<KeyCode>,
+    buttons: HashSet<MouseButton>,
+    mouse_position: Option<(f32, f32)>,
+    mouse_delta: (f32, f32),
+    reports_release: HashSet<KeyCode>,
+    repeat_timeout: Duration,
+}
+
+impl Default for InputState {
+    fn default() -> Self {
+        InputState::new(DEFAULT_REPEAT_TIMEOUT)
+    }
+}
+
+impl InputState {
+    pub fn new(repeat_timeout: Duration) -> InputState {
+        InputState {
+            pressed: HashMap::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+            buttons: HashSet::new(),
+            mouse_position: None,
+            mouse_delta: (0.0, 0.0),
+            reports_release: HashSet::new(),
+            repeat_timeout,
+        }
+    }
+
+    /// Whether the key is held. Letters are matched regardless of case
+    pub fn is_pressed(&self, code: KeyCode) -> bool {
+        self.pressed.contains_key(&normalize(code))
+    }
+
+    /// Whether the key has been pressed since the previous update
+    pub fn just_pressed(&self, code: KeyCode) -> bool {
+        self.just_pressed.contains(&normalize(code))
+    }
+
+    /// Whether the key has been released since the previous update
+    pub fn just_released(&self, code: KeyCode) -> bool {
+        self.just_released.contains(&normalize(code))
+    }
+
+    /// Held keys in no particular order
+    pub fn pressed_keys(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.pressed.keys().copied()
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    /// The last pointer position in renderer pixels, if the mouse has been used
+    pub fn mouse_position(&self) -> Option<(f32, f32)> {
+        self.mouse_position
+    }
+
+    /// Pointer movement in renderer pixels since the previous update
+    pub fn mouse_delta(&self) -> (f32, f32) {
+        self.mouse_delta
+    }
+
+    /// Whether the terminal reports releases of the key, so the repeat
+    /// timeout is not used for it. It is assumed after a release or
+    /// a repeat event, which are reported together
+    pub fn reports_release(&self, code: KeyCode) -> bool {
+        self.reports_release.contains(&normalize(code))
+    }
+
+    pub fn repeat_timeout(&self) -> Duration {
+        self.repeat_timeout
+    }
+
+    pub fn set_repeat_timeout(&mut self, timeout: Duration) {
+        self.repeat_timeout = timeout;
+    }
+
+    /// Applies the key event, received at `now`
+    pub fn handle_key(&mut self, input: &KeyboardInput, now: Instant) {
+        let code = normalize(input.code);
+
+        if input.kind != KeyEventKind::Press {
+            self.reports_release.insert(code);
+        }
+
+        match input.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                // Without release events repeats come as presses
+                if self.pressed.insert(code, now).is_none() {
+                    self.just_pressed.insert(code);
+                }
+            },
+            KeyEventKind::Release => {
+                if self.pressed.remove(&code).is_some() {
+                    self.just_released.insert(code);
+                }
+            },
+        }
+    }
+
+    /// Applies the mouse event
+    pub fn handle_mouse(&mut self, input: &MouseInput) {
+        match input.kind {
+            MouseEventKind::Down(button) | MouseEventKind::Drag(button) => {
+                self.buttons.insert(button);
+            },
+            MouseEventKind::Up(button) => {
+                self.buttons.remove(&button);
+            },
+            _ => {},
+        }
+
+        if let Some((x, y)) = self.mouse_position {
+            self.mouse_delta.0 += input.pixel.0 - x;
+            self.mouse_delta.1 += input.pixel.1 - y;
+        }
+
+        self.mouse_position = Some(input.pixel);
+    }
+
+    /// Releases keys, which haven't been pressed or repeated for the
+    /// repeat timeout before `now`. Keys, whose releases are reported
+    /// by the terminal, are kept until the release
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.repeat_timeout;
+        let reports_release = &self.reports_release;
+        let just_released = &mut self.just_released;
+
+        self.pressed.retain(|code, last| {
+            let held = reports_release.contains(code) || now.saturating_duration_since(*last) < timeout;
+            if !held {
+                just_released.insert(*code);
+            }
+
+            held
+        });
+    }
+
+    /// Forgets changes since the previous update. It is called after every update
+    pub fn end_update(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+        self.mouse_delta = (0.0, 0.0);
+    }
+}
+
+/// Letters are tracked in lower case, so Shift doesn't split a key in two
+fn normalize(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+        code => code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, kind: KeyEventKind) -> KeyboardInput {
+        KeyboardInput::new(code, kind)
+    }
+
+    #[test]
+    fn press_and_release() {
+        let mut state = InputState::default();
+        let now = Instant::now();
+
+        state.handle_key(&key(KeyCode::Char('W'), KeyEventKind::Press), now);
+        assert!(state.is_pressed(KeyCode::Char('w')));
+        assert!(state.just_pressed(KeyCode::Char('w')));
+
+        state.end_update();
+        assert!(state.is_pressed(KeyCode::Char('w')));
+        assert!(!state.just_pressed(KeyCode::Char('w')));
+
+        state.handle_key(&key(KeyCode::Char('w'), KeyEventKind::Release), now);
+        assert!(!state.is_pressed(KeyCode::Char('w')));
+        assert!(state.just_released(KeyCode::Char('w')));
+
+        state.end_update();
+        assert!(!state.just_released(KeyCode::Char('w')));
+    }
+
+    #[test]
+    fn repeats_keep_key_held() {
+        let mut state = InputState::new(Duration::from_millis(100));
+        let start = Instant::now();
+
+        state.handle_key(&key(KeyCode::Up, KeyEventKind::Press), start);
+        state.end_update();
+
+        // Without release events, repeats come as presses
+        for ms in [50, 100, 150] {
+            let now = start + Duration::from_millis(ms);
+
+            state.handle_key(&key(KeyCode::Up, KeyEventKind::Press), now);
+            state.expire(now);
+
+            assert!(state.is_pressed(KeyCode::Up));
+            assert!(!state.just_pressed(KeyCode::Up));
+            assert!(!state.just_released(KeyCode::Up));
+
+            state.end_update();
+        }
+    }
+
+    #[test]
+    fn expire_releases_after_timeout() {
+        let mut state = InputState::new(Duration::from_millis(100));
+        let start = Instant::now();
+
+        state.handle_key(&key(KeyCode::Left, KeyEventKind::Press), start);
+        state.end_update();
+
+        state.expire(start + Duration::from_millis(99));
+        assert!(state.is_pressed(KeyCode::Left));
+
+        state.expire(start + Duration::from_millis(100));
+        assert!(!state.is_pressed(KeyCode::Left));
+        assert!(state.just_released(KeyCode::Left));
+    }
+
+    #[test]
+    fn expire_is_tracked_per_key() {
+        let mut state = InputState::new(Duration::from_millis(100));
+        let start = Instant::now();
+
+        // The terminal reports the arrow release, but not the one of the letter
+        state.handle_key(&key(KeyCode::Up, KeyEventKind::Press), start);
+        state.handle_key(&key(KeyCode::Up, KeyEventKind::Release), start);
+        state.handle_key(&key(KeyCode::Char('a'), KeyEventKind::Press), start);
+        state.handle_key(&key(KeyCode::Down, KeyEventKind::Press), start);
+        state.handle_key(&key(KeyCode::Down, KeyEventKind::Repeat), start);
+
+        assert!(state.reports_release(KeyCode::Up));
+        assert!(state.reports_release(KeyCode::Down));
+        assert!(!state.reports_release(KeyCode::Char('a')));
+
+        state.expire(start + Duration::from_secs(1));
+        assert!(!state.is_pressed(KeyCode::Char('a')));
+        assert!(state.is_pressed(KeyCode::Down));
+    }
+
+    #[test]
+    fn end_update_resets_mouse_delta() {
+        let mut state = InputState::default();
+        let mut input = MouseInput {
+            kind: MouseEventKind::Moved,
+            modifiers: crossterm::event::KeyModifiers::NONE,
+            cell: (0, 0),
+            pixel: (1.0, 2.0),
+        };
+
+        state.handle_mouse(&input);
+        assert_eq!(state.mouse_delta(), (0.0, 0.0));
+
+        input.kind = MouseEventKind::Drag(MouseButton::Left);
+        input.pixel = (4.0, 0.0);
+        state.handle_mouse(&input);
+
+        assert_eq!(state.mouse_position(), Some((4.0, 0.0)));
+        assert_eq!(state.mouse_delta(), (3.0, -2.0));
+        assert!(state.is_button_pressed(MouseButton::Left));
+
+        state.end_update();
+        assert_eq!(state.mouse_delta(), (0.0, 0.0));
+        assert!(state.is_button_pressed(MouseButton::Left));
+    }
+}
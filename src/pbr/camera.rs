@@ -4,7 +4,7 @@
 use bytemuck::{Pod, Zeroable};
 use nalgebra_glm as glm;
 use serde::{Deserialize, Serialize};
-use crate::render::{hal::Padding, TransformationType};
+use crate::{render::{hal::Padding, Renderer, TransformationType}, utils::Size};
 use super::transform::Transform;
 
 /// A matrix to convert OpenGL coordinate system to WGPU coordinate system.
@@ -22,6 +22,18 @@ pub struct Camera {
     fovy: f32,
     near: f32,
     far: f32,
+    #[serde(default = "default_aspect_correction")]
+    aspect_correction: bool,
+    #[serde(default = "default_pixel_aspect")]
+    pixel_aspect: f32,
+}
+
+fn default_aspect_correction() -> bool {
+    true
+}
+
+fn default_pixel_aspect() -> f32 {
+    1.0
 }
 
 impl Camera {
@@ -32,6 +44,8 @@ impl Camera {
             fovy: 45.0,
             near: 0.1,
             far: 100.0,
+            aspect_correction: true,
+            pixel_aspect: 1.0,
         }
     }
 
@@ -45,7 +59,7 @@ impl Camera {
             TransformationType::LookAt => translation_matrix * rotation_matrix,
         };
 
-        let projection = glm::perspective(self.aspect, self.fovy, self.near, self.far);
+        let projection = glm::perspective(self.corrected_aspect(), self.fovy, self.near, self.far);
 
         OPENGL_TO_WGPU_MATRIX * projection * view
     }
@@ -54,6 +68,45 @@ impl Camera {
     pub fn set_aspect(&mut self, aspect: f32) {
         self.aspect = aspect;
     }
+
+    /// Sets the aspect ratio to the one of the renderer and the pixel aspect to
+    /// the one of its terminal cells. It is called, when the renderer is resized
+    pub fn fit(&mut self, renderer: &Renderer) {
+        let Size::Renderer(width, height) = renderer.size() else { unreachable!() };
+
+        self.aspect = width as f32 / height.max(1) as f32;
+        self.pixel_aspect = renderer.pixel_aspect();
+    }
+
+    /// Sets the width to height ratio of a single canvas pixel on the screen,
+    /// which is given by [`Renderer::pixel_aspect`]
+    pub fn set_pixel_aspect(&mut self, pixel_aspect: f32) {
+        self.pixel_aspect = pixel_aspect;
+    }
+
+    pub fn pixel_aspect(&self) -> f32 {
+        self.pixel_aspect
+    }
+
+    /// Enables correction of the aspect ratio with the pixel aspect, so
+    /// the aspect is given in canvas pixels, which are not square in most
+    /// output modes. It is enabled by default
+    pub fn set_aspect_correction(&mut self, enabled: bool) {
+        self.aspect_correction = enabled;
+    }
+
+    pub fn aspect_correction(&self) -> bool {
+        self.aspect_correction
+    }
+
+    /// Aspect ratio of the view on the screen
+    pub fn corrected_aspect(&self) -> f32 {
+        if self.aspect_correction {
+            self.aspect * self.pixel_aspect
+        } else {
+            self.aspect
+        }
+    }
 }
 
 /// Uniform data structure for the camera, used for passing camera information to the GPU.
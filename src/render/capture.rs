@@ -0,0 +1,148 @@
+//! Capture module saves rendered frames to image files
+//! at the output resolution of the renderer
+
+use std::{
+    fs::File,
+    io::BufWriter,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    imageops::{self, FilterType},
+    Delay, RgbaImage,
+};
+
+/// Format of the captured file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// A single frame in PNG
+    Png,
+    /// Animated GIF. Colors are reduced to 256 per frame
+    Gif,
+    /// Animated PNG
+    Apng,
+}
+
+/// Captures a range of frames and saves them to a file, when the
+/// last frame of the range is captured. Frames are kept in memory
+/// until then.
+///
+/// Frames are captured from the output texture: the same pixels, which
+/// are encoded for the terminal. With supersampling, this is the
+/// downsampled canvas, not the supersampled one
+#[derive(Debug, Clone)]
+pub struct FrameCapture {
+    path: PathBuf,
+    format: CaptureFormat,
+    frames: Range<u64>,
+    frame_delay: Duration,
+    captured: Vec<RgbaImage>,
+}
+
+impl FrameCapture {
+    /// Captures a single frame to a PNG file
+    pub fn screenshot(path: impl AsRef<Path>, frame: u64) -> FrameCapture {
+        FrameCapture::new(path, CaptureFormat::Png, frame..frame + 1, Duration::ZERO)
+    }
+
+    /// Captures the frame range to an animated GIF, showing every frame for `frame_delay`
+    pub fn gif(path: impl AsRef<Path>, frames: Range<u64>, frame_delay: Duration) -> FrameCapture {
+        FrameCapture::new(path, CaptureFormat::Gif, frames, frame_delay)
+    }
+
+    /// Captures the frame range to an animated PNG, showing every frame for `frame_delay`
+    pub fn apng(path: impl AsRef<Path>, frames: Range<u64>, frame_delay: Duration) -> FrameCapture {
+        FrameCapture::new(path, CaptureFormat::Apng, frames, frame_delay)
+    }
+
+    /// Creates a capture of the frame range. Only the first frame is
+    /// saved in [`CaptureFormat::Png`]
+    pub fn new(path: impl AsRef<Path>, format: CaptureFormat, frames: Range<u64>, frame_delay: Duration) -> FrameCapture {
+        FrameCapture {
+            path: path.as_ref().to_path_buf(),
+            format,
+            frames,
+            frame_delay,
+            captured: vec![],
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    pub fn frames(&self) -> &Range<u64> {
+        &self.frames
+    }
+
+    /// Whether the frame with the number is in the captured range
+    pub fn wants(&self, frame: u64) -> bool {
+        self.frames.contains(&frame)
+    }
+
+    /// Whether all frames of the range are captured
+    pub fn is_complete(&self, frame: u64) -> bool {
+        frame + 1 >= self.frames.end
+    }
+
+    /// Adds a frame. Frames of a different size are scaled
+    /// to the size of the first captured frame
+    pub fn push(&mut self, image: RgbaImage) {
+        let image = match self.captured.first() {
+            Some(first) if first.dimensions() != image.dimensions() => {
+                imageops::resize(&image, first.width(), first.height(), FilterType::Nearest)
+            },
+            _ => image,
+        };
+
+        self.captured.push(image);
+    }
+
+    /// Writes captured frames to the file. Nothing is written,
+    /// if no frames have been captured
+    pub fn save(self) -> anyhow::Result<()> {
+        let Some(first) = self.captured.first() else { return Ok(()) };
+
+        match self.format {
+            CaptureFormat::Png => first.save_with_format(&self.path, image::ImageFormat::Png)?,
+            CaptureFormat::Gif => {
+                let file = BufWriter::new(File::create(&self.path)?);
+                let delay = Delay::from_saturating_duration(self.frame_delay);
+
+                let mut encoder = GifEncoder::new_with_speed(file, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                encoder.encode_frames(
+                    self.captured
+                        .into_iter()
+                        .map(|image| image::Frame::from_parts(image, 0, 0, delay)),
+                )?;
+            },
+            CaptureFormat::Apng => {
+                let file = BufWriter::new(File::create(&self.path)?);
+                let delay = self.frame_delay.as_millis().min(u16::MAX as u128) as u16;
+
+                let mut encoder = png::Encoder::new(file, first.width(), first.height());
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(self.captured.len() as u32, 0)?;
+                encoder.set_frame_delay(delay, 1000)?;
+
+                let mut writer = encoder.write_header()?;
+                for image in &self.captured {
+                    writer.write_image_data(image.as_raw())?;
+                }
+
+                writer.finish()?;
+            },
+        }
+
+        Ok(())
+    }
+}
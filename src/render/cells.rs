@@ -0,0 +1,231 @@
+//! Cells module contains a compute pass, which reduces the canvas to
+//! terminal cells on the GPU, so only the cells are read back
+
+use bytemuck::{Pod, Zeroable};
+use image::Rgb;
+
+use crate::{
+    include_wgsl,
+    terminal::{
+        cell::{Cell, CellGrid},
+        encoder::{braille::BRAILLE_BLANK, BlockSymbols, BrailleThreshold, OutputMode},
+    },
+    utils::CellResolution,
+};
+
+use super::{
+    hal::{
+        buffer::{Buffer, BufferResourceDescriptor},
+        pipeline::{ComputePipelineDescriptor, Pipeline},
+        resource::ShaderResource,
+        texture::{TextureResourceDescriptor, TextureResourceUsage},
+    },
+    readback::{ReadbackRing, DEFAULT_STAGING_BUFFERS},
+    ComputeDescriptor, Renderer,
+};
+
+/// Flags in the high byte of [`GpuCell::bg`], which mark set colors.
+/// Colors without them are the default ones
+const FG_SET: u32 = 0x0100_0000;
+const BG_SET: u32 = 0x0200_0000;
+
+/// Size of the compute workgroup in cells
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Cell, as it is written by the compute shader. Colors are packed as
+/// `0xXXBBGGRR`, where the high byte of `fg` is the glyph index and the high
+/// byte of `bg` holds the [`FG_SET`] and [`BG_SET`] flags. Glyph indices
+/// depend on the output mode and are mapped to characters by [`glyphs`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Zeroable, Pod)]
+pub struct GpuCell {
+    pub fg: u32,
+    pub bg: u32,
+}
+
+impl GpuCell {
+    /// Unpacks the cell with the glyph table of the output mode
+    fn to_cell(self, glyphs: &[char; 256]) -> Cell {
+        let unpack = |color: u32, set: u32| {
+            (self.bg & set != 0).then_some(Rgb([color as u8, (color >> 8) as u8, (color >> 16) as u8]))
+        };
+
+        Cell {
+            glyph: glyphs[(self.fg >> 24) as usize],
+            fg: unpack(self.fg, FG_SET),
+            bg: unpack(self.bg, BG_SET),
+        }
+    }
+}
+
+/// Characters of the glyph indices, written by the shader in the mode.
+/// Half blocks and blocks are indexed by the mask of covered parts,
+/// Braille characters by their dots
+fn glyphs(mode: u32) -> [char; 256] {
+    std::array::from_fn(|index| {
+        let index = index as u32;
+
+        match mode {
+            0 | 1 if index < 16 => BlockSymbols::Quadrant.glyph(index),
+            2 if index < 64 => BlockSymbols::Sextant.glyph(index),
+            3 | 4 if index > 0 => char::from_u32(BRAILLE_BLANK + index).unwrap(),
+            _ => ' ',
+        }
+    })
+}
+
+/// Push constants of the compute shader
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+struct CellParams {
+    mode: u32,
+    cols: u32,
+    rows: u32,
+    threshold: u32,
+}
+
+/// Encodes the canvas into terminal cells with a compute shader. It supports
+/// half-block, block and Braille output modes, and gives the same cells as
+/// their CPU encoders. Readback takes 8 bytes per cell, which is as much as
+/// the canvas pixels of a half-block cell and a quarter of a Braille cell.
+/// Cells are read back through a [`ReadbackRing`], so a frame can be read
+/// while the next one renders
+pub struct CellPass {
+    output_mode: OutputMode,
+    params: CellParams,
+    glyphs: [char; 256],
+    texture_id: wgpu::Id<wgpu::Texture>,
+    pipeline: Pipeline,
+    resource: ShaderResource,
+    cells: Buffer<GpuCell>,
+    readback: ReadbackRing<GpuCell>,
+}
+
+impl CellPass {
+    /// Creates a pass for the current output of the renderer. Returns `None`,
+    /// if the output mode is not supported or the canvas has no cells
+    pub fn new(renderer: &Renderer, output_mode: &OutputMode) -> Option<CellPass> {
+        let (mode, threshold, resolution) = match output_mode {
+            OutputMode::HalfBlock => (0, 0, CellResolution::HALF_BLOCK),
+            OutputMode::Blocks(BlockSymbols::Quadrant) => (1, 0, CellResolution::new(2, 2)),
+            OutputMode::Blocks(BlockSymbols::Sextant) => (2, 0, CellResolution::new(2, 3)),
+            OutputMode::Braille(BrailleThreshold::Luminance(threshold)) => (3, *threshold, CellResolution::new(2, 4)),
+            OutputMode::Braille(BrailleThreshold::Edge(threshold)) => (4, *threshold, CellResolution::new(2, 4)),
+            _ => return None,
+        };
+
+        let output = renderer.output();
+        let canvas_size = (output.texture().descriptor().width, output.texture().descriptor().height);
+
+        let params = CellParams {
+            mode,
+            cols: canvas_size.0 / resolution.width,
+            rows: canvas_size.1 / resolution.height,
+            threshold: threshold as u32,
+        };
+
+        let count = (params.cols * params.rows) as usize;
+        if count == 0 {
+            return None;
+        }
+
+        let cells = Buffer::new(
+            renderer,
+            count,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        );
+
+        let readback = ReadbackRing::new(renderer, DEFAULT_STAGING_BUFFERS, count);
+
+        let resource = ShaderResource::builder()
+            .set_label("Cell pass")
+            .add_texture(output.texture(), &TextureResourceDescriptor {
+                usage: TextureResourceUsage::TEXTURE,
+                sample_type: Some(wgpu::TextureSampleType::Float { filterable: true }),
+                sampler_binding_type: None,
+            })
+            .add_buffer(&cells, &BufferResourceDescriptor {
+                visibility: wgpu::ShaderStages::COMPUTE,
+                buffer_type: wgpu::BufferBindingType::Storage { read_only: false },
+            })
+            .build(renderer);
+
+        let pipeline = Pipeline::new_compute(renderer, &ComputePipelineDescriptor {
+            shader: include_wgsl!("cells.wgsl"),
+            bindings: &[&resource],
+            label: "Cell pass",
+        });
+
+        Some(CellPass {
+            output_mode: output_mode.clone(),
+            params,
+            glyphs: glyphs(mode),
+            texture_id: output.texture().texture().global_id(),
+            pipeline,
+            resource,
+            cells,
+            readback,
+        })
+    }
+
+    /// Whether the pass is created for the output mode and the current output
+    /// texture. The texture is recreated, when the renderer is resized
+    pub fn is_compatible(&self, renderer: &Renderer, output_mode: &OutputMode) -> bool {
+        self.output_mode == *output_mode && self.texture_id == renderer.output().texture().texture().global_id()
+    }
+
+    /// Encodes the canvas and reads the cells back into the grid.
+    /// It blocks until the GPU is done
+    pub fn encode(&mut self, renderer: &Renderer, grid: &mut CellGrid) {
+        self.submit(renderer);
+
+        while self.pending() > 0 {
+            self.read_oldest(renderer, grid);
+        }
+    }
+
+    /// Encodes the canvas and starts reading the cells back without
+    /// waiting for the GPU. The cells are read with [`CellPass::read_oldest`]
+    pub fn submit(&mut self, renderer: &Renderer) {
+        let mut draw_ctx = renderer.draw_ctx();
+        renderer.downsample(&mut draw_ctx);
+
+        {
+            let mut compute_pass = draw_ctx.compute_pass();
+
+            compute_pass.compute(ComputeDescriptor {
+                instance_data: Some(&self.params),
+                pipeline: &self.pipeline,
+                shader_resources: &[&self.resource],
+                workgroups: (
+                    self.params.cols.div_ceil(WORKGROUP_SIZE),
+                    self.params.rows.div_ceil(WORKGROUP_SIZE),
+                    1,
+                ),
+            });
+        }
+
+        draw_ctx.copy_buffer(&self.cells, 0, self.readback.buffer(), 0, *self.cells.capacity() as u64);
+
+        let submission = draw_ctx.apply(renderer.output(), renderer);
+        self.readback.submit(submission);
+    }
+
+    /// Number of submitted frames, which are not read yet
+    pub fn pending(&self) -> usize {
+        self.readback.pending()
+    }
+
+    /// Reads cells of the oldest submitted frame into the grid, waiting for it
+    /// if needed. Returns `false`, if there is nothing to read or it failed
+    pub fn read_oldest(&mut self, renderer: &Renderer, grid: &mut CellGrid) -> bool {
+        let Some(bytes) = self.readback.read_oldest(renderer) else { return false };
+
+        grid.resize(self.params.cols as u16, self.params.rows as u16);
+        for (cell, bytes) in grid.cells_mut().iter_mut().zip(bytes.chunks_exact(size_of::<GpuCell>())) {
+            *cell = bytemuck::pod_read_unaligned::<GpuCell>(bytes).to_cell(&self.glyphs);
+        }
+
+        true
+    }
+}
@@ -0,0 +1,252 @@
+// Reduces the canvas to terminal cells: one invocation per cell.
+// Every mode matches its CPU encoder in `terminal::encoder`
+
+const MODE_HALF_BLOCK: u32 = 0u;
+const MODE_QUADRANT: u32 = 1u;
+const MODE_SEXTANT: u32 = 2u;
+const MODE_BRAILLE_LUMINANCE: u32 = 3u;
+const MODE_BRAILLE_EDGE: u32 = 4u;
+
+// Colors are packed as 0x01BBGGRR, zero means the default color
+const COLOR_SET: u32 = 0x01000000u;
+const COLOR_MASK: u32 = 0x00ffffffu;
+
+// Flags of set colors in the high byte of the packed background
+const FG_SET: u32 = 0x01000000u;
+const BG_SET: u32 = 0x02000000u;
+
+// Pixels with lower alpha are transparent
+const ALPHA_THRESHOLD: i32 = 128;
+
+// Glyphs are written as indices, which are mapped to characters on the CPU.
+// Half blocks use the quadrant masks, blocks use the mask of covered parts
+// and Braille uses the dots
+const BLANK: u32 = 0u;
+const UPPER_HALF_BLOCK: u32 = 3u;
+const LOWER_HALF_BLOCK: u32 = 12u;
+
+const BRAILLE_DOTS = array<u32, 8>(
+    0x01u, 0x08u,
+    0x02u, 0x10u,
+    0x04u, 0x20u,
+    0x40u, 0x80u,
+);
+
+struct Params {
+    mode: u32,
+    cols: u32,
+    rows: u32,
+    threshold: u32,
+}
+
+struct Cell {
+    fg: u32,
+    bg: u32,
+    glyph: u32,
+}
+
+// Cell as it is read back: colors with the glyph index
+// in the high byte of `fg` and the flags in the one of `bg`
+struct PackedCell {
+    fg: u32,
+    bg: u32,
+}
+
+var<push_constant> params: Params;
+
+@group(0) @binding(0)
+var canvas: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<storage, read_write> cells: array<PackedCell>;
+
+fn pixel(x: u32, y: u32) -> vec3<i32> {
+    let color = textureLoad(canvas, vec2<u32>(x, y), 0);
+
+    return vec3<i32>(round(color.rgb * 255.0));
+}
+
+fn is_transparent(x: u32, y: u32) -> bool {
+    let alpha = textureLoad(canvas, vec2<u32>(x, y), 0).a;
+
+    return i32(round(alpha * 255.0)) < ALPHA_THRESHOLD;
+}
+
+fn pack(color: vec3<i32>) -> u32 {
+    let c = vec3<u32>(clamp(color, vec3<i32>(0), vec3<i32>(255)));
+
+    return COLOR_SET | c.r | (c.g << 8u) | (c.b << 16u);
+}
+
+fn luminance(color: vec3<i32>) -> i32 {
+    return (299 * color.r + 587 * color.g + 114 * color.b) / 1000;
+}
+
+fn half_block(col: u32, row: u32) -> Cell {
+    let top = pixel(col, row * 2u);
+    let bottom = pixel(col, row * 2u + 1u);
+    let top_transparent = is_transparent(col, row * 2u);
+    let bottom_transparent = is_transparent(col, row * 2u + 1u);
+
+    if top_transparent && bottom_transparent {
+        return Cell(0u, 0u, BLANK);
+    }
+
+    if top_transparent {
+        return Cell(pack(bottom), 0u, LOWER_HALF_BLOCK);
+    }
+
+    if bottom_transparent {
+        return Cell(pack(top), 0u, UPPER_HALF_BLOCK);
+    }
+
+    return Cell(pack(top), pack(bottom), UPPER_HALF_BLOCK);
+}
+
+// Finds the split of pixels into foreground and background,
+// which minimizes the squared error of their mean colors
+fn blocks(col: u32, row: u32, rows_per_cell: u32) -> Cell {
+    var pixels: array<vec3<i32>, 6>;
+    let count = 2u * rows_per_cell;
+    var opaque = 0u;
+
+    for (var i = 0u; i < count; i++) {
+        let x = col * 2u + i % 2u;
+        let y = row * rows_per_cell + i / 2u;
+
+        pixels[i] = pixel(x, y);
+        if !is_transparent(x, y) {
+            opaque |= 1u << i;
+        }
+    }
+
+    let full = (1u << count) - 1u;
+
+    // Opaque pixels of partly transparent cells are drawn
+    // with their average color over the default background
+    if opaque == 0u {
+        return Cell(0u, 0u, BLANK);
+    }
+
+    if opaque != full {
+        var sum = vec3<i32>(0);
+        var opaque_count = 0;
+
+        for (var i = 0u; i < count; i++) {
+            if ((opaque >> i) & 1u) != 0u {
+                sum += pixels[i];
+                opaque_count += 1;
+            }
+        }
+
+        return Cell(pack(sum / opaque_count), 0u, opaque);
+    }
+
+    var best_mask = 0u;
+    var best_means = array<vec3<i32>, 2>(vec3<i32>(0), vec3<i32>(0));
+    var best_error = 0x7fffffff;
+
+    // Complementary masks give the same split with swapped colors, so only
+    // masks without the last pixel are checked
+    for (var mask = 0u; mask <= (full >> 1u); mask++) {
+        var sums = array<vec3<i32>, 2>(vec3<i32>(0), vec3<i32>(0));
+        var counts = array<i32, 2>(0, 0);
+
+        for (var i = 0u; i < count; i++) {
+            let side = (mask >> i) & 1u;
+            counts[side] += 1;
+            sums[side] += pixels[i];
+        }
+
+        var means = array<vec3<i32>, 2>(
+            sums[0] / max(counts[0], 1),
+            sums[1] / max(counts[1], 1),
+        );
+
+        var error = 0;
+        for (var i = 0u; i < count; i++) {
+            let difference = pixels[i] - means[(mask >> i) & 1u];
+            error += dot(difference, difference);
+        }
+
+        if error < best_error {
+            best_error = error;
+            best_mask = mask;
+            best_means = means;
+        }
+    }
+
+    var fg = 0u;
+    if best_mask != 0u {
+        fg = pack(best_means[1]);
+    }
+
+    return Cell(fg, pack(best_means[0]), best_mask);
+}
+
+fn is_drawn(x: u32, y: u32) -> bool {
+    if is_transparent(x, y) {
+        return false;
+    }
+
+    let value = luminance(pixel(x, y));
+    let threshold = i32(params.threshold);
+
+    if params.mode == MODE_BRAILLE_LUMINANCE {
+        return value > threshold;
+    }
+
+    let size = textureDimensions(canvas);
+    let right = luminance(pixel(min(x + 1u, size.x - 1u), y));
+    let bottom = luminance(pixel(x, min(y + 1u, size.y - 1u)));
+
+    return max(abs(value - right), abs(value - bottom)) > threshold;
+}
+
+fn braille(col: u32, row: u32) -> Cell {
+    var braille_dots = BRAILLE_DOTS;
+    var dots = 0u;
+    var sum = vec3<i32>(0);
+    var count = 0;
+
+    for (var i = 0u; i < 8u; i++) {
+        let x = col * 2u + i % 2u;
+        let y = row * 4u + i / 2u;
+
+        if is_drawn(x, y) {
+            dots |= braille_dots[i];
+            sum += pixel(x, y);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        return Cell(0u, 0u, BLANK);
+    }
+
+    return Cell(pack(sum / count), 0u, dots);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let col = id.x;
+    let row = id.y;
+
+    if col >= params.cols || row >= params.rows {
+        return;
+    }
+
+    var cell: Cell;
+    switch params.mode {
+        case MODE_HALF_BLOCK: { cell = half_block(col, row); }
+        case MODE_QUADRANT: { cell = blocks(col, row, 2u); }
+        case MODE_SEXTANT: { cell = blocks(col, row, 3u); }
+        default: { cell = braille(col, row); }
+    }
+
+    let flags = select(0u, FG_SET, cell.fg != 0u) | select(0u, BG_SET, cell.bg != 0u);
+    cells[row * params.cols + col] = PackedCell(
+        (cell.fg & COLOR_MASK) | (cell.glyph << 24u),
+        (cell.bg & COLOR_MASK) | flags,
+    );
+}
@@ -0,0 +1,102 @@
+//! Downsample module contains a compute pass, which reduces the
+//! supersampled canvas to the output resolution
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::include_wgsl;
+
+use super::{
+    hal::{
+        pipeline::{ComputePipelineDescriptor, Pipeline},
+        resource::ShaderResource,
+        texture::{Texture, TextureResourceDescriptor, TextureResourceUsage},
+    },
+    ComputeDescriptor, DrawContext, Renderer,
+};
+
+/// Size of the compute workgroup in pixels
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Filter, which reduces the supersampled canvas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DownsampleFilter {
+    /// Average of the pixels, covered by the output pixel
+    #[default]
+    Box,
+    /// Lanczos-3 filter. It keeps edges sharper than the box filter,
+    /// but takes more samples per pixel
+    Lanczos,
+}
+
+/// Push constants of the compute shader
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Zeroable, Pod)]
+struct DownsampleParams {
+    kernel: u32,
+    factor: u32,
+}
+
+/// Reduces the `source` texture into the `output` texture, which is
+/// `factor` times smaller
+pub(crate) struct DownsamplePass {
+    params: DownsampleParams,
+    size: (u32, u32),
+    pipeline: Pipeline,
+    resource: ShaderResource,
+}
+
+impl DownsamplePass {
+    pub(crate) fn new(
+        renderer: &Renderer,
+        source: &Texture,
+        output: &Texture,
+        factor: u32,
+        filter: DownsampleFilter,
+    ) -> DownsamplePass {
+        let resource = ShaderResource::builder()
+            .set_label("Downsample pass")
+            .add_texture(source, &TextureResourceDescriptor {
+                usage: TextureResourceUsage::TEXTURE,
+                sample_type: Some(wgpu::TextureSampleType::Float { filterable: true }),
+                sampler_binding_type: None,
+            })
+            .add_texture(output, &TextureResourceDescriptor {
+                usage: TextureResourceUsage::STORAGE,
+                sample_type: None,
+                sampler_binding_type: None,
+            })
+            .build(renderer);
+
+        let pipeline = Pipeline::new_compute(renderer, &ComputePipelineDescriptor {
+            shader: include_wgsl!("downsample.wgsl"),
+            bindings: &[&resource],
+            label: "Downsample pass",
+        });
+
+        DownsamplePass {
+            params: DownsampleParams {
+                kernel: filter as u32,
+                factor,
+            },
+            size: (output.descriptor().width, output.descriptor().height),
+            pipeline,
+            resource,
+        }
+    }
+
+    /// Records the pass into the draw context
+    pub(crate) fn record(&self, draw_ctx: &mut DrawContext) {
+        let mut compute_pass = draw_ctx.compute_pass();
+
+        compute_pass.compute(ComputeDescriptor {
+            instance_data: Some(&self.params),
+            pipeline: &self.pipeline,
+            shader_resources: &[&self.resource],
+            workgroups: (
+                self.size.0.div_ceil(WORKGROUP_SIZE),
+                self.size.1.div_ceil(WORKGROUP_SIZE),
+                1,
+            ),
+        });
+    }
+}
@@ -0,0 +1,99 @@
+// Reduces the supersampled canvas to the output resolution:
+// one invocation per output pixel
+
+const KERNEL_BOX: u32 = 0u;
+
+// Lanczos-3: the kernel reaches three output pixels from the center
+const LANCZOS_RADIUS: f32 = 3.0;
+
+struct Params {
+    kernel: u32,
+    factor: u32,
+}
+
+var<push_constant> params: Params;
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var output: texture_storage_2d<rgba8unorm, write>;
+
+fn sinc(x: f32) -> f32 {
+    if abs(x) < 1e-5 {
+        return 1.0;
+    }
+
+    let angle = 3.14159265 * x;
+    return sin(angle) / angle;
+}
+
+fn lanczos(x: f32) -> f32 {
+    if abs(x) >= LANCZOS_RADIUS {
+        return 0.0;
+    }
+
+    return sinc(x) * sinc(x / LANCZOS_RADIUS);
+}
+
+// Averages the block of source texels, covered by the output pixel
+fn box_filter(pixel: vec2<u32>) -> vec4<f32> {
+    var sum = vec4<f32>(0.0);
+
+    for (var y = 0u; y < params.factor; y++) {
+        for (var x = 0u; x < params.factor; x++) {
+            sum += textureLoad(source, pixel * params.factor + vec2<u32>(x, y), 0);
+        }
+    }
+
+    return sum / f32(params.factor * params.factor);
+}
+
+// Weights source texels around the center of the output pixel with the
+// Lanczos kernel, scaled to the output resolution
+fn lanczos_filter(pixel: vec2<u32>) -> vec4<f32> {
+    let factor = f32(params.factor);
+    let last = vec2<i32>(textureDimensions(source)) - 1;
+    let center = (vec2<f32>(pixel) + 0.5) * factor;
+    let first = vec2<i32>(floor(center));
+    let reach = i32(ceil(LANCZOS_RADIUS * factor));
+
+    var sum = vec4<f32>(0.0);
+    var weights = 0.0;
+
+    for (var dy = -reach; dy < reach; dy++) {
+        for (var dx = -reach; dx < reach; dx++) {
+            let texel = first + vec2<i32>(dx, dy);
+            let distance = (vec2<f32>(texel) + 0.5 - center) / factor;
+            let weight = lanczos(distance.x) * lanczos(distance.y);
+
+            if weight == 0.0 {
+                continue;
+            }
+
+            sum += weight * textureLoad(source, clamp(texel, vec2<i32>(0), last), 0);
+            weights += weight;
+        }
+    }
+
+    // Negative lobes overshoot near sharp edges
+    return clamp(sum / weights, vec4<f32>(0.0), vec4<f32>(1.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let pixel = id.xy;
+
+    if any(pixel >= textureDimensions(output)) {
+        return;
+    }
+
+    var color: vec4<f32>;
+    if params.kernel == KERNEL_BOX {
+        color = box_filter(pixel);
+    } else {
+        color = lanczos_filter(pixel);
+    }
+
+    textureStore(output, pixel, color);
+}
@@ -131,7 +131,7 @@ impl<T: Pod> Buffer<T> {
 }
 
 #[cfg(doc)]
-use crate::renderer::hal::pipeline::ShaderResource;
+use crate::render::hal::resource::ShaderResource;
 
 /// Used to bind generic buffer in [`ShaderResource`]
 pub struct BufferResourceDescriptor {
@@ -1,6 +1,6 @@
 use crate::render::{vertex::Vertex, Renderer};
 #[cfg(doc)]
-use crate::renderer::RenderPass;
+use crate::render::RenderPass;
 
 use super::{resource::ShaderResource, shader::Shader};
 
@@ -7,10 +7,35 @@ use serde::{Deserialize, Serialize};
 use vertex::Vertex;
 use nalgebra_glm as glm;
 
+pub mod capture;
+pub mod cells;
+pub mod downsample;
 pub mod error;
 pub mod hal;
+pub mod readback;
 pub mod vertex;
 
+/// Scales the size down, keeping its aspect ratio, so neither
+/// side exceeds the texture size limit
+fn fit_texture_limit(width: u32, height: u32) -> (u32, u32) {
+    let side = width.max(height);
+    if side <= MAX_TEXTURE_DIMENSION {
+        return (width, height);
+    }
+
+    let scale = |length: u32| ((length as u64 * MAX_TEXTURE_DIMENSION as u64 / side as u64) as u32).max(1);
+    log::warn!("Size {width}×{height} exceeds the texture size limit, using {}×{}", scale(width), scale(height));
+
+    (scale(width), scale(height))
+}
+
+/// Bytes per row of an RGBA texture copy, aligned to `COPY_BYTES_PER_ROW_ALIGNMENT` (256)
+pub fn padded_bytes_per_row(width: u32) -> u32 {
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    (width * 4).div_ceil(align) * align
+}
+
 pub mod prelude {
     pub use super::{
         Renderer,
@@ -36,6 +61,10 @@ pub mod prelude {
         shader::*,
         Padding,
     };
+    pub use super::capture::{CaptureFormat, FrameCapture};
+    pub use super::cells::CellPass;
+    pub use super::downsample::DownsampleFilter;
+    pub use super::readback::ReadbackRing;
     pub use super::error::RenderError;
 }
 
@@ -61,7 +90,11 @@ pub mod types {
 pub use include_wgsl_oil::include_wgsl_oil as include_wgsl_raw;
 pub use wgpu::include_spirv_raw;
 
-use crate::{fatal, utils::Size};
+use crate::{fatal, utils::{CellPixels, CellResolution, Size}};
+use downsample::{DownsampleFilter, DownsamplePass};
+
+/// Largest side of a texture, which the device is required to support
+const MAX_TEXTURE_DIMENSION: u32 = 4096;
 
 pub struct Renderer {
     width: u32,
@@ -71,15 +104,29 @@ pub struct Renderer {
     vertex_buffers: Vec<Buffer<Vertex>>,
     surface_texture: Option<Texture>,
     depth_texture: Option<Texture>,
+    /// Factor, the canvas is rendered at. It is lower than the requested
+    /// one, when the canvas would exceed the texture size limit
+    supersampling: u32,
+    requested_supersampling: u32,
+    downsample_filter: DownsampleFilter,
+    output_texture: Option<Texture>,
+    downsample_pass: Option<DownsamplePass>,
+    clear_color: wgpu::Color,
+    cell_resolution: CellResolution,
+    cell_pixels: CellPixels,
 }
 
 impl Renderer {
+    /// Creates a renderer of the given size. Terminal sizes are converted
+    /// with the half-block [`CellResolution`], until another one is set
     pub fn new(size: Size) -> anyhow::Result<Renderer> {
         let instance = Self::init_instance();
-        let adapter = Self::init_adapter(instance);
+        let adapter = Self::init_adapter(instance)?;
         let (device, queue) = Self::init_device(&adapter)?;
 
-        let Size::Renderer(width, height) = size.to_renderer() else { unreachable!() };
+        let cell_resolution = CellResolution::default();
+        let Size::Renderer(width, height) = size.to_renderer(cell_resolution) else { unreachable!() };
+        let (width, height) = fit_texture_limit(width, height);
 
         let mut renderer = Renderer {
             width,
@@ -89,56 +136,146 @@ impl Renderer {
             vertex_buffers: vec![],
             surface_texture: None,
             depth_texture: None,
+            supersampling: 1,
+            requested_supersampling: 1,
+            downsample_filter: DownsampleFilter::default(),
+            output_texture: None,
+            downsample_pass: None,
+            clear_color: wgpu::Color::BLACK,
+            cell_resolution,
+            cell_pixels: CellPixels::default(),
         };
 
-        renderer.surface_texture = Some(Texture::new(
-            &renderer,
-            TextureDescriptor {
-                width: renderer.width,
-                height: renderer.height,
-                filter: wgpu::FilterMode::Linear,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8Unorm,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT 
-                    | wgpu::TextureUsages::TEXTURE_BINDING
-                    | wgpu::TextureUsages::COPY_SRC,
-                depth: None,
-                mip_level_count: 1,
-                label: "Surface texture".to_string(),
-            }
-        ));
-
-        renderer.depth_texture = Some(Texture::new(
-            &renderer, 
-            TextureDescriptor {
-                width: renderer.width,
-                height: renderer.height,
-                filter: wgpu::FilterMode::Linear,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Depth32Float,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
-                depth: None,
-                mip_level_count: 1,
-                label: "Depth texture".to_string(),
-            },
-        ));
+        renderer.create_textures();
 
         Ok(renderer)
     }
 
+    /// Texture, which frames are rendered to. With supersampling it is
+    /// larger than [`Renderer::size`]
     pub fn canvas(&self) -> Canvas<'_> {
         Canvas { 
             texture: self.surface_texture.as_ref().unwrap() 
         }
     }
 
+    /// Texture of the renderer size, which is read back. Without
+    /// supersampling it is the canvas itself. It is up to date
+    /// after [`Renderer::downsample`]
+    pub fn output(&self) -> Canvas<'_> {
+        Canvas {
+            texture: self.output_texture.as_ref().or(self.surface_texture.as_ref()).unwrap()
+        }
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    /// Sets the color, which render passes clear the canvas to. It is black
+    /// by default. With a transparent color, the terminal background shows
+    /// through the pixels, which are not drawn
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    /// Number of canvas pixels per terminal cell, which
+    /// terminal sizes are converted with
+    pub fn cell_resolution(&self) -> CellResolution {
+        self.cell_resolution
+    }
+
+    /// Sets the number of canvas pixels per cell, usually to the
+    /// [`Terminal::cell_resolution`](crate::terminal::Terminal::cell_resolution).
+    /// The renderer is not resized
+    pub fn set_cell_resolution(&mut self, resolution: CellResolution) {
+        self.cell_resolution = resolution;
+    }
+
+    /// Size of a cell of the terminal, the renderer draws to, in screen pixels
+    pub fn cell_pixels(&self) -> CellPixels {
+        self.cell_pixels
+    }
+
+    pub fn set_cell_pixels(&mut self, pixels: CellPixels) {
+        self.cell_pixels = pixels;
+    }
+
+    /// Width to height ratio of a single output pixel on the screen. Cameras
+    /// correct their aspect with it, see [`Camera::fit`](crate::pbr::camera::Camera::fit)
+    pub fn pixel_aspect(&self) -> f32 {
+        self.cell_pixels.pixel_aspect(self.cell_resolution)
+    }
+
+    /// Number of canvas pixels per output pixel along each axis. It is lower
+    /// than the requested factor, while the canvas would exceed the texture
+    /// size limit at it
+    pub fn supersampling(&self) -> u32 {
+        self.supersampling
+    }
+
+    pub fn downsample_filter(&self) -> DownsampleFilter {
+        self.downsample_filter
+    }
+
+    /// Renders the canvas at `factor` times the renderer size and reduces it
+    /// with the `filter`. The factor is lowered for sizes, at which the canvas
+    /// would exceed the texture size limit, and restored for smaller ones.
+    /// Textures, including the depth texture, are recreated, so shader
+    /// resources, bound to them, must be rebuilt
+    pub fn set_supersampling(&mut self, factor: u32, filter: DownsampleFilter) {
+        self.requested_supersampling = factor.max(1);
+        self.downsample_filter = filter;
+
+        self.create_textures();
+    }
+
+    /// Records reduction of the supersampled canvas to the output texture.
+    /// Does nothing without supersampling
+    pub fn downsample(&self, draw_ctx: &mut DrawContext) {
+        if let Some(pass) = &self.downsample_pass {
+            pass.record(draw_ctx);
+        }
+    }
+
     pub fn depth_texture(&self) -> &Texture {
         self.depth_texture.as_ref().unwrap()
     }
 
+    /// Reads the output texture back from the GPU after downsampling.
+    /// It blocks until all submitted commands are done
+    pub fn read_canvas(&self) -> image::RgbaImage {
+        let unpadded_bytes_per_row = self.width * 4;
+        let padded_bytes_per_row = padded_bytes_per_row(self.width);
+
+        let buffer = Buffer::<u8>::new(
+            self,
+            (padded_bytes_per_row * self.height) as usize,
+            wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        );
+
+        let mut draw_ctx = self.draw_ctx();
+        self.downsample(&mut draw_ctx);
+
+        let output = self.output();
+        draw_ctx.copy_texture_to_buffer(output.texture(), &buffer);
+        draw_ctx.apply(output, self);
+
+        let data = pollster::block_on(buffer.read_bytes(self));
+        buffer.unmap();
+
+        let mut raw_data = Vec::with_capacity((unpadded_bytes_per_row * self.height) as usize);
+        for row in data.chunks_exact(padded_bytes_per_row as usize) {
+            raw_data.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+
+        image::RgbaImage::from_raw(self.width, self.height, raw_data).unwrap()
+    }
+
     pub fn draw_ctx(&self) -> DrawContext {
         DrawContext {
             encoder: self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default()),
+            clear_color: self.clear_color,
         }
     }
 
@@ -146,27 +283,19 @@ impl Renderer {
         self.resize_with(Size::Renderer(self.width, self.height));
     }
 
+    /// Resizes the renderer. Terminal sizes are converted with [`Renderer::cell_resolution`].
+    /// Sizes, which exceed the texture size limit, are scaled down to it
     pub fn resize_with(&mut self, size: Size) {
-        let Size::Renderer(width, height) = size.to_renderer() else { unreachable!() };
+        let Size::Renderer(width, height) = size.to_renderer(self.cell_resolution) else { unreachable!() };
 
         if width == 0 || height == 0 { return }
 
+        let (width, height) = fit_texture_limit(width, height);
+
         self.width = width;
         self.height = height;
 
-        if let Some(surface_texture) = &self.surface_texture {
-            let mut surface_descr = surface_texture.descriptor().clone();
-            surface_descr.width = self.width;
-            surface_descr.height = self.height;
-            self.surface_texture = Some(Texture::new(self, surface_descr));
-        }
-
-        if let Some(depth_texture) = &self.depth_texture {
-            let mut depth_descr = depth_texture.descriptor().clone();
-            depth_descr.width = self.width;
-            depth_descr.height = self.height;
-            self.depth_texture = Some(Texture::new(self, depth_descr));
-        }
+        self.create_textures();
     }
 
     pub fn create_vertex_buffer(&mut self, capacity: usize) -> BufferId {
@@ -199,9 +328,87 @@ impl Renderer {
         Size::Renderer(self.width, self.height)
     }
 
+    /// Creates the canvas, depth and output textures for the current size and
+    /// supersampling. The canvas and the depth texture are supersampled
+    fn create_textures(&mut self) {
+        let limit = (MAX_TEXTURE_DIMENSION / self.width.max(self.height).max(1)).max(1);
+        self.supersampling = self.requested_supersampling.min(limit);
+
+        if self.supersampling < self.requested_supersampling {
+            log::warn!("Supersampling {}x exceeds the texture size limit, using {}x", self.requested_supersampling, self.supersampling);
+        }
+
+        let width = self.width * self.supersampling;
+        let height = self.height * self.supersampling;
+
+        self.surface_texture = Some(Texture::new(
+            self,
+            TextureDescriptor {
+                width,
+                height,
+                filter: wgpu::FilterMode::Linear,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT 
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                depth: None,
+                mip_level_count: 1,
+                label: "Surface texture".to_string(),
+            }
+        ));
+
+        self.depth_texture = Some(Texture::new(
+            self, 
+            TextureDescriptor {
+                width,
+                height,
+                filter: wgpu::FilterMode::Linear,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Depth32Float,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                depth: None,
+                mip_level_count: 1,
+                label: "Depth texture".to_string(),
+            },
+        ));
+
+        if self.supersampling == 1 {
+            self.output_texture = None;
+            self.downsample_pass = None;
+            return;
+        }
+
+        let output_texture = Texture::new(
+            self,
+            TextureDescriptor {
+                width: self.width,
+                height: self.height,
+                filter: wgpu::FilterMode::Linear,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                depth: None,
+                mip_level_count: 1,
+                label: "Output texture".to_string(),
+            }
+        );
+
+        self.downsample_pass = Some(DownsamplePass::new(
+            self,
+            self.surface_texture.as_ref().unwrap(),
+            &output_texture,
+            self.supersampling,
+            self.downsample_filter,
+        ));
+        self.output_texture = Some(output_texture);
+    }
+
     fn init_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
         let mut required_limits = wgpu::Limits::downlevel_defaults();
-        required_limits.max_texture_dimension_2d = 4096;
+        required_limits.max_texture_dimension_2d = MAX_TEXTURE_DIMENSION;
         required_limits.max_texture_dimension_3d = 2048;
         required_limits.max_push_constant_size = 128;
         
@@ -219,14 +426,14 @@ impl Renderer {
         ))
     }
 
-    fn init_adapter(instance: wgpu::Instance) -> wgpu::Adapter {
+    fn init_adapter(instance: wgpu::Instance) -> anyhow::Result<wgpu::Adapter> {
         pollster::block_on(instance.request_adapter(
             &wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
                 compatible_surface: None,
                 force_fallback_adapter: false,
             }
-        )).unwrap()
+        )).ok_or_else(|| anyhow::anyhow!("No suitable graphics adapter found"))
     }
 
     fn init_instance() -> wgpu::Instance {
@@ -240,6 +447,7 @@ impl Renderer {
 
 pub struct DrawContext {
     encoder: wgpu::CommandEncoder,
+    clear_color: wgpu::Color,
 }
 
 impl DrawContext {
@@ -257,7 +465,7 @@ impl DrawContext {
                         view: canvas.view(),
                         resolve_target: None,
                         ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            load: wgpu::LoadOp::Clear(self.clear_color),
                             store: wgpu::StoreOp::Store,
                         },
                     })
@@ -335,9 +543,7 @@ impl DrawContext {
         from: &Texture,
         to: &Buffer<T>,
     ) {
-        let unpadded_bytes_per_row = from.descriptor().width * 4;
-        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT; // 256
-        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let padded_bytes_per_row = padded_bytes_per_row(from.descriptor().width);
 
         self.encoder.copy_texture_to_buffer(
             wgpu::ImageCopyTexture {
@@ -362,8 +568,9 @@ impl DrawContext {
         );
     }
 
-    pub fn apply(self, _canvas: Canvas<'_>, renderer: &Renderer) {        
-        renderer.queue.submit(std::iter::once(self.encoder.finish()));
+    /// Submits recorded commands. The index is used to wait for them
+    pub fn apply(self, _canvas: Canvas<'_>, renderer: &Renderer) -> wgpu::SubmissionIndex {
+        renderer.queue.submit(std::iter::once(self.encoder.finish()))
     }
 }
 
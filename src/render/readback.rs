@@ -0,0 +1,126 @@
+//! Readback module contains a ring of staging buffers, which read data
+//! back from the GPU without waiting for it right after the submission
+
+use std::{collections::VecDeque, sync::mpsc};
+
+use bytemuck::Pod;
+
+use super::{hal::buffer::Buffer, Renderer};
+
+/// Default number of staging buffers: one is mapped, while
+/// the next frame is copied into the other one
+pub const DEFAULT_STAGING_BUFFERS: usize = 2;
+
+/// Readback, which is submitted, but not read yet
+struct PendingReadback {
+    buffer: usize,
+    submission: wgpu::SubmissionIndex,
+    mapped: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+/// Ring of staging buffers. Data is copied into [`ReadbackRing::buffer`],
+/// the mapping is started with [`ReadbackRing::submit`], and the data is
+/// read later with [`ReadbackRing::read_oldest`], so the GPU keeps working
+/// on the next commands in between. Up to `count` readbacks can be pending
+pub struct ReadbackRing<T = u8> {
+    buffers: Vec<Buffer<T>>,
+    next: usize,
+    pending: VecDeque<PendingReadback>,
+}
+
+impl<T: Pod> ReadbackRing<T> {
+    /// Creates `count` staging buffers with the capacity of `len` elements
+    pub fn new(renderer: &Renderer, count: usize, len: usize) -> ReadbackRing<T> {
+        let buffers = (0..count.max(1))
+            .map(|_| Buffer::new(renderer, len, wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ))
+            .collect();
+
+        ReadbackRing {
+            buffers,
+            next: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Resizes the staging buffers to `len` elements. Pending readbacks are
+    /// waited for and dropped
+    pub fn resize(&mut self, renderer: &Renderer, len: usize) {
+        while let Some(readback) = self.pending.pop_front() {
+            self.wait(renderer, readback);
+        }
+
+        for buffer in &mut self.buffers {
+            buffer.resize(renderer, len);
+        }
+
+        self.next = 0;
+    }
+
+    /// Staging buffer, which the next readback is copied into
+    ///
+    /// # Panics
+    /// If all staging buffers are pending
+    pub fn buffer(&self) -> &Buffer<T> {
+        assert!(self.pending.len() < self.buffers.len(), "All staging buffers are pending");
+
+        &self.buffers[self.next]
+    }
+
+    /// Starts mapping [`ReadbackRing::buffer`], once the commands
+    /// of the submission are done, and moves to the next buffer
+    pub fn submit(&mut self, submission: wgpu::SubmissionIndex) {
+        let (tx, rx) = mpsc::channel();
+
+        self.buffers[self.next].inner().slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+
+        self.pending.push_back(PendingReadback {
+            buffer: self.next,
+            submission,
+            mapped: rx,
+        });
+
+        self.next = (self.next + 1) % self.buffers.len();
+    }
+
+    /// Number of submitted readbacks, which are not read yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Waits for the oldest pending readback and returns its bytes.
+    /// Only its submission is waited for, not the later ones
+    pub fn read_oldest(&mut self, renderer: &Renderer) -> Option<Vec<u8>> {
+        let readback = self.pending.pop_front()?;
+        let buffer = readback.buffer;
+
+        if !self.wait(renderer, readback) {
+            return None;
+        }
+
+        let data = self.buffers[buffer].inner().slice(..).get_mapped_range().to_vec();
+        self.buffers[buffer].unmap();
+
+        Some(data)
+    }
+
+    /// Waits until the buffer is mapped. Returns `false`, if the mapping failed
+    fn wait(&self, renderer: &Renderer, readback: PendingReadback) -> bool {
+        renderer.device.poll(wgpu::Maintain::WaitForSubmissionIndex(readback.submission));
+
+        loop {
+            match readback.mapped.try_recv() {
+                Ok(Ok(())) => return true,
+                Ok(Err(e)) => {
+                    log::error!("Failed to map staging buffer: {e}");
+                    return false;
+                },
+                Err(mpsc::TryRecvError::Empty) => {
+                    renderer.device.poll(wgpu::Maintain::Wait);
+                },
+                Err(mpsc::TryRecvError::Disconnected) => return false,
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Capabilities module detects features of the terminal, which
+//! are used to pick the best output mode
+
+use std::{fs, io::Write, path::PathBuf, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::{
+    color::ColorDepth,
+    encoder::{kitty::shared_memory_supported, KittyTransmission, OutputMode},
+    query::{numbers, query, replies, Reply},
+};
+
+/// Time to wait for the terminal replies, used by [`TermApp`](crate::app::TermApp)
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Kitty graphics query: a 1×1 image, which is checked but never stored
+const KITTY_QUERY: &str = "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\";
+
+/// Image ids of the Kitty queries, which read a 1×1 image from
+/// a temporary file and from a shared memory object
+const KITTY_TEMP_FILE_ID: u32 = 32;
+const KITTY_SHARED_MEMORY_ID: u32 = 33;
+
+/// Text area size in pixels (CSI 14t) and cell size in pixels (CSI 16t)
+const PIXEL_SIZE_QUERY: &str = "\x1b[14t\x1b[16t";
+
+/// DECRQM request for the synchronized output mode (2026)
+const SYNC_OUTPUT_QUERY: &str = "\x1b[?2026$p";
+
+/// Request for the current flags of the Kitty keyboard protocol
+const KEYBOARD_ENHANCEMENT_QUERY: &str = "\x1b[?u";
+
+/// Features of the terminal, detected at startup
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalCapabilities {
+    /// Number of colors, reported by `COLORTERM` and `TERM`
+    pub color_depth: ColorDepth,
+    /// Sixel graphics support, reported in the DA1 reply
+    pub sixel: bool,
+    /// Kitty graphics protocol support
+    pub kitty_graphics: bool,
+    /// Whether the terminal reads Kitty images from temporary files.
+    /// It can't, when it runs on another machine or in another container
+    pub kitty_temp_file: bool,
+    /// Whether the terminal reads Kitty images from shared memory
+    pub kitty_shared_memory: bool,
+    /// Text area size in pixels
+    pub window_pixels: Option<(u32, u32)>,
+    /// Cell size in pixels
+    pub cell_pixels: Option<(u32, u32)>,
+    /// Synchronized output (DEC mode 2026) support
+    pub synchronized_output: bool,
+    /// Kitty keyboard protocol support, which reports key
+    /// releases, repeats and unambiguous modifiers
+    pub keyboard_enhancement: bool,
+    /// Whether the terminal has answered any query
+    pub responsive: bool,
+}
+
+impl TerminalCapabilities {
+    /// Detects capabilities from the environment variables only
+    pub fn from_env() -> TerminalCapabilities {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default().to_lowercase();
+        let term = std::env::var("TERM").unwrap_or_default().to_lowercase();
+
+        let color_depth = if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else if term.is_empty() || term == "linux" || term == "dumb" || term.starts_with("vt") {
+            ColorDepth::Ansi16
+        } else if term.starts_with("xterm") || term.starts_with("screen") || term.starts_with("tmux") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        };
+
+        TerminalCapabilities {
+            color_depth,
+            ..Default::default()
+        }
+    }
+
+    /// Detects capabilities from the environment and terminal replies.
+    /// Queries are written to `out`, which must be a terminal, and the
+    /// replies are awaited for no longer than `timeout`
+    pub fn probe(out: &mut impl Write, timeout: Duration) -> TerminalCapabilities {
+        let requests = ProbeRequests::new();
+        let received = query(out, requests.as_str(), timeout);
+
+        TerminalCapabilities::from_replies(&received)
+    }
+
+    /// Detects capabilities from the environment and the received replies.
+    /// Replies to other queries are ignored
+    pub(crate) fn from_replies(received: &[u8]) -> TerminalCapabilities {
+        let mut capabilities = TerminalCapabilities::from_env();
+
+        for reply in replies(received) {
+            capabilities.responsive = true;
+
+            match reply {
+                Reply::Csi(params, b'c') if params.starts_with(b"?") => {
+                    capabilities.sixel = numbers(&params[1..]).contains(&4);
+                },
+                Reply::Csi(params, b't') => match numbers(params)[..] {
+                    [4, height, width] if width > 0 && height > 0 => {
+                        capabilities.window_pixels = Some((width, height));
+                    },
+                    [6, height, width] if width > 0 && height > 0 => {
+                        capabilities.cell_pixels = Some((width, height));
+                    },
+                    _ => {},
+                },
+                Reply::Csi(params, b'y') if params.starts_with(b"?") && params.ends_with(b"$") => {
+                    let params = numbers(&params[1..params.len() - 1]);
+
+                    // 1 and 2 mean the mode is set or reset, 3 means it is permanently set
+                    capabilities.synchronized_output = matches!(params[..], [2026, 1..=3]);
+                },
+                Reply::Csi(params, b'u') if params.starts_with(b"?") => {
+                    capabilities.keyboard_enhancement = true;
+                },
+                Reply::Apc(data) => match kitty_reply(data) {
+                    Some((31, ok)) => capabilities.kitty_graphics = ok,
+                    Some((KITTY_TEMP_FILE_ID, ok)) => capabilities.kitty_temp_file = ok,
+                    Some((KITTY_SHARED_MEMORY_ID, ok)) => capabilities.kitty_shared_memory = ok,
+                    _ => {},
+                },
+                _ => {},
+            }
+        }
+
+        if capabilities.cell_pixels.is_none() {
+            capabilities.cell_pixels = capabilities.window_pixels
+                .zip(crossterm::terminal::size().ok())
+                .filter(|(_, (cols, rows))| *cols > 0 && *rows > 0)
+                .map(|((width, height), (cols, rows))| (width / cols as u32, height / rows as u32));
+        }
+
+        capabilities
+    }
+
+    /// The fastest Kitty transmission, which the terminal has read
+    /// in a query. Pixels are sent directly, if it has read none
+    pub fn kitty_transmission(&self) -> KittyTransmission {
+        if self.kitty_shared_memory {
+            KittyTransmission::SharedMemory
+        } else if self.kitty_temp_file {
+            KittyTransmission::TempFile
+        } else {
+            KittyTransmission::Direct
+        }
+    }
+
+    /// The best output mode, supported by the terminal
+    pub fn output_mode(&self) -> OutputMode {
+        if self.kitty_graphics {
+            OutputMode::Kitty(KittyTransmission::Auto)
+        } else if self.sixel {
+            OutputMode::Sixel
+        } else {
+            OutputMode::HalfBlock
+        }
+    }
+}
+
+/// Image id and whether the terminal has answered `OK` in a Kitty graphics reply
+fn kitty_reply(data: &[u8]) -> Option<(u32, bool)> {
+    let keys = data.strip_prefix(b"Gi=")?;
+    let end = keys.iter().position(|b| *b == b',' || *b == b';')?;
+    let id = std::str::from_utf8(&keys[..end]).ok()?.parse().ok()?;
+
+    Some((id, data.ends_with(b";OK")))
+}
+
+/// Queries of the capability probe. Kitty queries of temporary files and
+/// shared memory need files with a 1×1 image, which are removed, when
+/// the requests are dropped, unless the terminal has deleted them
+pub(crate) struct ProbeRequests {
+    requests: String,
+    files: Vec<PathBuf>,
+}
+
+impl ProbeRequests {
+    pub(crate) fn new() -> ProbeRequests {
+        let mut requests = [KITTY_QUERY, PIXEL_SIZE_QUERY, SYNC_OUTPUT_QUERY, KEYBOARD_ENHANCEMENT_QUERY].concat();
+        let mut files = vec![];
+
+        let mut media = vec![(KITTY_TEMP_FILE_ID, KittyTransmission::TempFile)];
+        if shared_memory_supported() {
+            media.push((KITTY_SHARED_MEMORY_ID, KittyTransmission::SharedMemory));
+        }
+
+        for (id, transmission) in media {
+            match transmission.write(&[0; 3]) {
+                Ok((medium, name)) => {
+                    let payload = STANDARD.encode(name.as_bytes());
+                    requests += &format!("\x1b_Gi={id},s=1,v=1,a=q,t={medium},f=24;{payload}\x1b\\");
+
+                    files.push(KittyTransmission::path(medium, &name));
+                },
+                Err(e) => log::warn!("Failed to write a Kitty query with {transmission:?}: {e}"),
+            }
+        }
+
+        ProbeRequests { requests, files }
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.requests
+    }
+}
+
+impl Drop for ProbeRequests {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+    use crate::terminal::theme::TerminalTheme;
+
+    #[test]
+    fn batched_replies() {
+        let received = b"\x1b_Gi=31;OK\x1b\\\x1b[6;16;8t\x1b]11;rgb:ffff/0000/0000\x1b\\\x1b[?2026;2$y\x1b[?62;4c";
+
+        let capabilities = TerminalCapabilities::from_replies(received);
+        assert!(capabilities.kitty_graphics && capabilities.sixel && capabilities.synchronized_output);
+        assert_eq!(capabilities.cell_pixels, Some((8, 16)));
+
+        let theme = TerminalTheme::from_replies(received);
+        assert_eq!(theme.background, Some(Rgb([255, 0, 0])));
+    }
+
+    #[test]
+    fn kitty_media() {
+        let capabilities = TerminalCapabilities::from_replies(b"\x1b_Gi=31;OK\x1b\\\x1b[?62c");
+        assert_eq!(capabilities.kitty_transmission(), KittyTransmission::Direct);
+
+        let received = b"\x1b_Gi=31;OK\x1b\\\x1b_Gi=32;OK\x1b\\\x1b_Gi=33;ENOENT:No such file\x1b\\\x1b[?62c";
+        let capabilities = TerminalCapabilities::from_replies(received);
+        assert!(capabilities.kitty_temp_file && !capabilities.kitty_shared_memory);
+        assert_eq!(capabilities.kitty_transmission(), KittyTransmission::TempFile);
+    }
+}
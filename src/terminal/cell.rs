@@ -0,0 +1,280 @@
+//! Cell module contains character cells, produced by text-based
+//! output modes, and the code writing them to the terminal
+
+use std::io::{self, Write};
+
+use image::Rgb;
+
+use super::color::{Quantizer, TermColor};
+
+/// A single character cell of the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    /// Character, drawn in the cell
+    pub glyph: char,
+    /// Foreground color. `None` keeps the terminal default
+    pub fg: Option<Rgb<u8>>,
+    /// Background color. `None` keeps the terminal default
+    pub bg: Option<Rgb<u8>>,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            glyph: ' ',
+            fg: None,
+            bg: None,
+        }
+    }
+}
+
+/// Row-major grid of terminal cells
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CellGrid {
+    cols: u16,
+    rows: u16,
+    cells: Vec<Cell>,
+}
+
+impl CellGrid {
+    /// Creates a grid of the given size, filled with empty cells
+    pub fn new(cols: u16, rows: u16) -> CellGrid {
+        CellGrid {
+            cols,
+            rows,
+            cells: vec![Cell::default(); cols as usize * rows as usize],
+        }
+    }
+
+    pub fn cols(&self) -> u16 {
+        self.cols
+    }
+
+    pub fn rows(&self) -> u16 {
+        self.rows
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [Cell] {
+        &mut self.cells
+    }
+
+    /// Changes the grid size, keeping the allocation if possible.
+    /// Contents of the grid are unspecified afterwards
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.cols = cols;
+        self.rows = rows;
+        self.cells.resize(cols as usize * rows as usize, Cell::default());
+    }
+
+    pub fn get(&self, col: u16, row: u16) -> &Cell {
+        &self.cells[row as usize * self.cols as usize + col as usize]
+    }
+
+    pub fn set(&mut self, col: u16, row: u16, cell: Cell) {
+        self.cells[row as usize * self.cols as usize + col as usize] = cell;
+    }
+}
+
+/// Unchanged cells between two changed runs, which are rewritten
+/// instead of moving the cursor over them
+const MAX_MERGED_GAP: usize = 2;
+
+/// Writes cells row by row. Every row starts with a cursor move, relative
+/// to the `origin` column and row of the grid. Colors are converted to
+/// the color depth of the quantizer
+pub fn write_cells(grid: &CellGrid, quantizer: &Quantizer, origin: (u16, u16), out: &mut Vec<u8>) -> io::Result<()> {
+    let mut writer = CellWriter::new(quantizer);
+
+    for (row, cells) in grid.cells().chunks(grid.cols().max(1) as usize).enumerate() {
+        write!(out, "\x1b[{};{}H", origin.1 as usize + row + 1, origin.0 as usize + 1)?;
+
+        for cell in cells {
+            writer.write_cell(cell, out)?;
+        }
+    }
+
+    writer.finish(out);
+
+    Ok(())
+}
+
+/// Writes only the cells, which differ from the previous grid. Every run
+/// of changed cells starts with a cursor move, relative to the `origin`
+/// column and row of the grid. Both grids must be of the same size
+pub fn write_cells_diff(
+    previous: &CellGrid,
+    grid: &CellGrid,
+    quantizer: &Quantizer,
+    origin: (u16, u16),
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    let mut writer = CellWriter::new(quantizer);
+    let cols = grid.cols() as usize;
+    let changed = |i: usize| previous.cells[i] != grid.cells[i];
+
+    for row in 0..grid.rows() as usize {
+        let line = row * cols;
+        let mut col = 0;
+
+        while col < cols {
+            if !changed(line + col) {
+                col += 1;
+                continue;
+            }
+
+            let start = col;
+            let mut end = col + 1;
+
+            loop {
+                while end < cols && changed(line + end) {
+                    end += 1;
+                }
+
+                let gap = (end..cols).take_while(|x| !changed(line + x)).count();
+                if gap > MAX_MERGED_GAP || end + gap == cols {
+                    break;
+                }
+
+                end += gap;
+            }
+
+            write!(out, "\x1b[{};{}H", origin.1 as usize + row + 1, origin.0 as usize + start + 1)?;
+            for cell in &grid.cells[line + start..line + end] {
+                writer.write_cell(cell, out)?;
+            }
+
+            col = end;
+        }
+    }
+
+    writer.finish(out);
+
+    Ok(())
+}
+
+/// Streaming writer of cells, which keeps track of the current
+/// SGR colors and emits only the changed ones
+#[derive(Debug, Clone)]
+pub struct CellWriter<'a> {
+    quantizer: &'a Quantizer,
+    fg: Option<TermColor>,
+    bg: Option<TermColor>,
+}
+
+impl<'a> CellWriter<'a> {
+    /// Creates a writer, which doesn't assume any current colors
+    pub fn new(quantizer: &'a Quantizer) -> CellWriter<'a> {
+        CellWriter {
+            quantizer,
+            fg: None,
+            bg: None,
+        }
+    }
+
+    /// Writes the cell at the current cursor position
+    pub fn write_cell(&mut self, cell: &Cell, out: &mut Vec<u8>) -> io::Result<()> {
+        let fg = self.quantizer.color(cell.fg);
+        let bg = self.quantizer.color(cell.bg);
+
+        // Foreground color is invisible in blank cells
+        let fg_changed = self.fg != Some(fg) && cell.glyph != ' ';
+        let bg_changed = self.bg != Some(bg);
+
+        if fg_changed || bg_changed {
+            out.extend_from_slice(b"\x1b[");
+
+            if fg_changed {
+                fg.write_sgr(out, true)?;
+                self.fg = Some(fg);
+            }
+
+            if bg_changed {
+                if fg_changed {
+                    out.push(b';');
+                }
+
+                bg.write_sgr(out, false)?;
+                self.bg = Some(bg);
+            }
+
+            out.push(b'm');
+        }
+
+        let mut glyph = [0; 4];
+        out.extend_from_slice(cell.glyph.encode_utf8(&mut glyph).as_bytes());
+
+        Ok(())
+    }
+
+    /// Resets colors to the terminal defaults
+    pub fn finish(&mut self, out: &mut Vec<u8>) {
+        out.extend_from_slice(b"\x1b[0m");
+        self.fg = Some(TermColor::Default);
+        self.bg = Some(TermColor::Default);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes the difference of a blank row and the row with `x` in the columns
+    fn diff(cols: u16, changed: &[u16], origin: (u16, u16)) -> String {
+        let previous = CellGrid::new(cols, 1);
+        let mut grid = previous.clone();
+        for &col in changed {
+            grid.set(col, 0, Cell { glyph: 'x', ..Cell::default() });
+        }
+
+        let mut out = vec![];
+        write_cells_diff(&previous, &grid, &Quantizer::default(), origin, &mut out).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn diff_of_single_cell() {
+        assert_eq!(diff(8, &[2], (0, 0)), "\x1b[1;3H\x1b[39;49mx\x1b[0m");
+        assert_eq!(diff(8, &[2], (3, 4)), "\x1b[5;6H\x1b[39;49mx\x1b[0m");
+        assert_eq!(diff(8, &[], (0, 0)), "\x1b[0m");
+    }
+
+    #[test]
+    fn diff_merges_short_gaps() {
+        assert_eq!(diff(8, &[1, 4], (0, 0)), "\x1b[1;2H\x1b[39;49mx  x\x1b[0m");
+        assert_eq!(diff(8, &[1, 5], (0, 0)), "\x1b[1;2H\x1b[39;49mx\x1b[1;6Hx\x1b[0m");
+    }
+
+    #[test]
+    fn diff_skips_trailing_unchanged_cells() {
+        assert_eq!(diff(8, &[5], (0, 0)), "\x1b[1;6H\x1b[39;49mx\x1b[0m");
+        assert_eq!(diff(8, &[4, 7], (0, 0)), "\x1b[1;5H\x1b[39;49mx  x\x1b[0m");
+    }
+
+    #[test]
+    fn writer_coalesces_colors() {
+        let (red, green, blue) = (Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255]));
+        let cells = [
+            Cell { glyph: '▀', fg: Some(red), bg: Some(blue) },
+            Cell { glyph: '▀', fg: Some(red), bg: Some(blue) },
+            Cell { glyph: ' ', fg: None, bg: Some(blue) },
+            Cell { glyph: '▀', fg: Some(green), bg: Some(blue) },
+        ];
+
+        let quantizer = Quantizer::default();
+        let mut writer = CellWriter::new(&quantizer);
+        let mut out = vec![];
+        for cell in &cells {
+            writer.write_cell(cell, &mut out).unwrap();
+        }
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[38;2;255;0;0;48;2;0;0;255m▀▀ \x1b[38;2;0;255;0m▀",
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Color module contains quantization of cell colors for terminals
+//! without truecolor support
+
+use std::io::{self, Write};
+
+use image::Rgb;
+
+use super::cell::CellGrid;
+
+/// Stock xterm colors of the 16-color palette
+pub const XTERM_PALETTE: [Rgb<u8>; 16] = [
+    Rgb([0x00, 0x00, 0x00]),
+    Rgb([0xcd, 0x00, 0x00]),
+    Rgb([0x00, 0xcd, 0x00]),
+    Rgb([0xcd, 0xcd, 0x00]),
+    Rgb([0x00, 0x00, 0xee]),
+    Rgb([0xcd, 0x00, 0xcd]),
+    Rgb([0x00, 0xcd, 0xcd]),
+    Rgb([0xe5, 0xe5, 0xe5]),
+    Rgb([0x7f, 0x7f, 0x7f]),
+    Rgb([0xff, 0x00, 0x00]),
+    Rgb([0x00, 0xff, 0x00]),
+    Rgb([0xff, 0xff, 0x00]),
+    Rgb([0x5c, 0x5c, 0xff]),
+    Rgb([0xff, 0x00, 0xff]),
+    Rgb([0x00, 0xff, 0xff]),
+    Rgb([0xff, 0xff, 0xff]),
+];
+
+/// Channel levels of the xterm 6×6×6 color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// 4×4 ordered dithering matrix
+const BAYER_4X4: [[i32; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+/// Color, as it is written in SGR sequences
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TermColor {
+    /// Default color of the terminal
+    #[default]
+    Default,
+    /// 24-bit color
+    Rgb(Rgb<u8>),
+    /// Index in the 256-color palette
+    Indexed(u8),
+    /// Index in the 16-color palette. Written with SGR 30–37 and
+    /// 90–97, which work even in the Linux console
+    Ansi(u8),
+}
+
+impl TermColor {
+    /// Writes SGR parameters of the color without the CSI and the final `m`
+    pub fn write_sgr(self, out: &mut Vec<u8>, foreground: bool) -> io::Result<()> {
+        let base = if foreground { 30 } else { 40 };
+
+        match self {
+            TermColor::Default => write!(out, "{}", base + 9),
+            TermColor::Rgb(Rgb([r, g, b])) => write!(out, "{};2;{r};{g};{b}", base + 8),
+            TermColor::Indexed(index) => write!(out, "{};5;{index}", base + 8),
+            TermColor::Ansi(index @ 0..8) => write!(out, "{}", base + index),
+            TermColor::Ansi(index) => write!(out, "{}", base + 60 + (index & 7)),
+        }
+    }
+}
+
+/// Number of colors, which the terminal is able to show
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// 24-bit colors
+    #[default]
+    TrueColor,
+    /// xterm 256-color palette: 6×6×6 color cube and 24 grays
+    Ansi256,
+    /// 16 ANSI colors
+    Ansi16,
+}
+
+/// Dithering, applied when colors are reduced to a palette
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dithering {
+    /// Colors are replaced with the nearest palette entry
+    #[default]
+    None,
+    /// Quantization error is diffused to neighbouring cells
+    FloydSteinberg,
+    /// 4×4 Bayer matrix offsets colors before quantization
+    Bayer,
+}
+
+/// Reduces cell colors to the palette of the color depth
+#[derive(Debug, Clone)]
+pub struct Quantizer {
+    depth: ColorDepth,
+    dithering: Dithering,
+    palette: [Rgb<u8>; 16],
+    errors: Vec<[i32; 3]>,
+}
+
+impl Default for Quantizer {
+    fn default() -> Self {
+        Quantizer::new(ColorDepth::default(), Dithering::default())
+    }
+}
+
+impl Quantizer {
+    pub fn new(depth: ColorDepth, dithering: Dithering) -> Quantizer {
+        Quantizer {
+            depth,
+            dithering,
+            palette: XTERM_PALETTE,
+            errors: vec![],
+        }
+    }
+
+    pub fn depth(&self) -> ColorDepth {
+        self.depth
+    }
+
+    pub fn set_depth(&mut self, depth: ColorDepth) {
+        self.depth = depth;
+    }
+
+    pub fn dithering(&self) -> Dithering {
+        self.dithering
+    }
+
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        self.dithering = dithering;
+    }
+
+    /// Colors, which the 16-color palette is assumed to have
+    pub fn palette(&self) -> &[Rgb<u8>; 16] {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: [Rgb<u8>; 16]) {
+        self.palette = palette;
+    }
+
+    /// Replaces cell colors with the palette colors. Foreground and background
+    /// colors are dithered as separate images, so errors don't spread between
+    /// them: in block and Braille modes they are not neighbouring pixels
+    pub fn quantize(&mut self, grid: &mut CellGrid) {
+        if self.depth == ColorDepth::TrueColor {
+            return;
+        }
+
+        let cols = grid.cols() as usize;
+        let spread = match self.depth {
+            ColorDepth::Ansi16 => 128,
+            _ => 48,
+        };
+
+        // Errors of the current and the next color rows with a column of padding on both sides
+        let mut errors = std::mem::take(&mut self.errors);
+
+        for background in [false, true] {
+            errors.clear();
+            errors.resize((cols + 2) * 2, [0; 3]);
+
+            for row in 0..grid.rows() as usize {
+                let (current, next) = errors.split_at_mut(cols + 2);
+                current.copy_from_slice(next);
+                next.fill([0; 3]);
+
+                // Bayer rows follow the pixel rows of the half-block output
+                let bayer_row = (row * 2 + background as usize) % 4;
+
+                for col in 0..cols {
+                    let cell = &mut grid.cells_mut()[row * cols + col];
+                    let color = if background { &mut cell.bg } else { &mut cell.fg };
+                    let Some(Rgb(rgb)) = *color else { continue };
+
+                    let mut value = [0; 3];
+                    for c in 0..3 {
+                        value[c] = rgb[c] as i32 + match self.dithering {
+                            Dithering::None => 0,
+                            Dithering::FloydSteinberg => current[col + 1][c],
+                            Dithering::Bayer => (BAYER_4X4[bayer_row][col % 4] * 2 - 15) * spread / 32,
+                        };
+                    }
+
+                    let target = Rgb(value.map(|v| v.clamp(0, 255) as u8));
+                    let (_, nearest) = self.nearest(target);
+                    *color = Some(nearest);
+
+                    if self.dithering == Dithering::FloydSteinberg {
+                        for c in 0..3 {
+                            let error = value[c] - nearest.0[c] as i32;
+
+                            current[col + 2][c] += error * 7 / 16;
+                            next[col][c] += error * 3 / 16;
+                            next[col + 1][c] += error * 5 / 16;
+                            next[col + 2][c] += error / 16;
+                        }
+                    }
+                }
+            }
+        }
+
+        self.errors = errors;
+    }
+
+    /// Terminal color for the cell color. Colors are expected
+    /// to be already quantized, see [`Quantizer::quantize`]
+    pub fn color(&self, color: Option<Rgb<u8>>) -> TermColor {
+        let Some(rgb) = color else { return TermColor::Default };
+
+        match self.depth {
+            ColorDepth::TrueColor => TermColor::Rgb(rgb),
+            ColorDepth::Ansi256 => TermColor::Indexed(self.nearest(rgb).0),
+            ColorDepth::Ansi16 => TermColor::Ansi(self.nearest(rgb).0),
+        }
+    }
+
+    /// Palette index and color, nearest to the given one
+    pub fn nearest(&self, rgb: Rgb<u8>) -> (u8, Rgb<u8>) {
+        match self.depth {
+            ColorDepth::TrueColor => (0, rgb),
+            ColorDepth::Ansi256 => nearest_256(rgb),
+            ColorDepth::Ansi16 => self.palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, color)| distance(rgb, **color))
+                .map(|(i, color)| (i as u8, *color))
+                .unwrap(),
+        }
+    }
+}
+
+/// Nearest color of the xterm color cube or the gray ramp.
+/// System colors are skipped, because terminals redefine them
+fn nearest_256(rgb: Rgb<u8>) -> (u8, Rgb<u8>) {
+    let level = |v: u8| match v {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (v as usize - 35) / 40,
+    };
+
+    let [r, g, b] = rgb.0.map(level);
+    let cube = Rgb([CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]]);
+    let cube_index = (16 + r * 36 + g * 6 + b) as u8;
+
+    let average = rgb.0.iter().map(|c| *c as u32).sum::<u32>() / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + gray_step * 10;
+    let gray = Rgb([gray_level; 3]);
+
+    if distance(rgb, gray) < distance(rgb, cube) {
+        (232 + gray_step, gray)
+    } else {
+        (cube_index, cube)
+    }
+}
+
+fn distance(a: Rgb<u8>, b: Rgb<u8>) -> u32 {
+    a.0.iter()
+        .zip(b.0)
+        .map(|(a, b)| (*a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_256_cube() {
+        assert_eq!(nearest_256(Rgb([255, 0, 0])), (196, Rgb([255, 0, 0])));
+        assert_eq!(nearest_256(Rgb([0, 0, 255])), (21, Rgb([0, 0, 255])));
+        assert_eq!(nearest_256(Rgb([95, 135, 175])), (67, Rgb([95, 135, 175])));
+        assert_eq!(nearest_256(Rgb([100, 130, 180])), (67, Rgb([95, 135, 175])));
+    }
+
+    #[test]
+    fn nearest_256_grays() {
+        assert_eq!(nearest_256(Rgb([0, 0, 0])), (16, Rgb([0, 0, 0])));
+        assert_eq!(nearest_256(Rgb([255, 255, 255])), (231, Rgb([255, 255, 255])));
+        assert_eq!(nearest_256(Rgb([8, 8, 8])), (232, Rgb([8, 8, 8])));
+        assert_eq!(nearest_256(Rgb([120, 120, 120])), (243, Rgb([118, 118, 118])));
+        assert_eq!(nearest_256(Rgb([238, 238, 238])), (255, Rgb([238, 238, 238])));
+    }
+
+    #[test]
+    fn nearest_16_uses_palette() {
+        let mut quantizer = Quantizer::new(ColorDepth::Ansi16, Dithering::None);
+        assert_eq!(quantizer.nearest(Rgb([250, 10, 10])), (9, Rgb([0xff, 0, 0])));
+
+        let mut palette = XTERM_PALETTE;
+        palette[1] = Rgb([250, 10, 10]);
+        quantizer.set_palette(palette);
+
+        assert_eq!(quantizer.nearest(Rgb([250, 10, 10])), (1, Rgb([250, 10, 10])));
+    }
+
+    #[test]
+    fn dithering_keeps_planes_apart() {
+        let quantize = |bg: Rgb<u8>| {
+            let mut grid = CellGrid::new(4, 3);
+            for cell in grid.cells_mut() {
+                cell.fg = Some(Rgb([100, 100, 100]));
+                cell.bg = Some(bg);
+            }
+
+            Quantizer::new(ColorDepth::Ansi16, Dithering::FloydSteinberg).quantize(&mut grid);
+            grid.cells().iter().map(|cell| cell.fg).collect::<Vec<_>>()
+        };
+
+        // Errors of the background don't change the foreground
+        assert_eq!(quantize(Rgb([0, 0, 0])), quantize(Rgb([200, 200, 200])));
+    }
+
+    #[test]
+    fn sgr_parameters() {
+        let mut out = vec![];
+        TermColor::Rgb(Rgb([1, 2, 3])).write_sgr(&mut out, true).unwrap();
+        out.push(b' ');
+        TermColor::Indexed(196).write_sgr(&mut out, false).unwrap();
+        out.push(b' ');
+        TermColor::Ansi(9).write_sgr(&mut out, true).unwrap();
+        out.push(b' ');
+        TermColor::Default.write_sgr(&mut out, false).unwrap();
+
+        assert_eq!(out, b"38;2;1;2;3 48;5;196 91 49");
+    }
+}
@@ -0,0 +1,96 @@
+use std::io;
+
+use image::Rgb;
+
+use crate::{terminal::cell::{Cell, CellGrid}, utils::CellResolution};
+
+use super::{is_transparent, luminance, Encoded, Encoder, Frame};
+
+/// Characters ordered from the darkest to the brightest
+pub const DEFAULT_ASCII_RAMP: &str = " .:-=+*#%@";
+
+/// Draws every cell as a character of the density ramp, picked by
+/// the average luminance of the cell pixels. Transparent pixels count
+/// as dark. Optionally the cell is colored with the average color of
+/// its opaque pixels
+#[derive(Debug, Clone)]
+pub struct AsciiEncoder {
+    ramp: Vec<char>,
+    color: bool,
+    grid: CellGrid,
+}
+
+impl AsciiEncoder {
+    /// Creates a new encoder. Empty ramp is replaced with [`DEFAULT_ASCII_RAMP`]
+    pub fn new(ramp: &str, color: bool) -> AsciiEncoder {
+        let ramp = if ramp.is_empty() { DEFAULT_ASCII_RAMP } else { ramp };
+
+        AsciiEncoder {
+            ramp: ramp.chars().collect(),
+            color,
+            grid: CellGrid::default(),
+        }
+    }
+}
+
+impl Default for AsciiEncoder {
+    fn default() -> Self {
+        AsciiEncoder::new(DEFAULT_ASCII_RAMP, false)
+    }
+}
+
+impl Encoder for AsciiEncoder {
+    fn resolution(&self) -> CellResolution {
+        CellResolution::HALF_BLOCK
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>> {
+        let resolution = self.resolution();
+        let cols = (frame.width() / resolution.width) as u16;
+        let rows = (frame.height() / resolution.height) as u16;
+        let count = resolution.width * resolution.height;
+        self.grid.resize(cols, rows);
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let mut sum = [0u32; 3];
+                let mut luma = 0;
+                let mut opaque = 0;
+
+                for dy in 0..resolution.height {
+                    for dx in 0..resolution.width {
+                        let pixel = frame.get_pixel(
+                            col as u32 * resolution.width + dx,
+                            row as u32 * resolution.height + dy,
+                        );
+
+                        if is_transparent(pixel) {
+                            continue;
+                        }
+
+                        opaque += 1;
+                        luma += luminance(pixel) as u32;
+                        for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                            *sum += channel as u32;
+                        }
+                    }
+                }
+
+                if opaque == 0 {
+                    self.grid.set(col, row, Cell::default());
+                    continue;
+                }
+
+                let level = (luma / count) as usize * self.ramp.len() / 256;
+
+                self.grid.set(col, row, Cell {
+                    glyph: self.ramp[level],
+                    fg: self.color.then(|| Rgb(sum.map(|s| (s / opaque) as u8))),
+                    bg: None,
+                });
+            }
+        }
+
+        Ok(Encoded::Cells(&self.grid))
+    }
+}
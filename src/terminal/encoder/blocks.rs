@@ -0,0 +1,244 @@
+use std::io;
+
+use image::Rgb;
+
+use crate::{terminal::cell::{Cell, CellGrid}, utils::CellResolution};
+
+use super::{is_transparent, Encoded, Encoder, Frame};
+
+/// Quadrant characters, indexed by the mask of covered quadrants:
+/// bit 0 is top left, bit 1 is top right, bit 2 is bottom left
+/// and bit 3 is bottom right
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀', '▖', '▌', '▞', '▛',
+    '▗', '▚', '▐', '▜', '▄', '▙', '▟', '█',
+];
+
+/// First character of the sextant range in the Symbols for Legacy Computing block
+const SEXTANT_FIRST: u32 = 0x1FB00;
+
+/// Set of block characters, which split a cell into equal parts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockSymbols {
+    /// 2×2 quadrant characters from the Block Elements block
+    #[default]
+    Quadrant,
+    /// 2×3 sextant characters, added in Unicode 13. Terminal
+    /// font must support Symbols for Legacy Computing
+    Sextant,
+}
+
+impl BlockSymbols {
+    fn rows(self) -> u32 {
+        match self {
+            BlockSymbols::Quadrant => 2,
+            BlockSymbols::Sextant => 3,
+        }
+    }
+
+    /// Character, which covers parts of the cell, set in the mask.
+    /// Bits go row by row, from left to right
+    pub(crate) fn glyph(self, mask: u32) -> char {
+        match self {
+            BlockSymbols::Quadrant => QUADRANTS[mask as usize],
+            BlockSymbols::Sextant => match mask {
+                0 => ' ',
+                // Left and right halves are not repeated in the sextant range
+                21 => '▌',
+                42 => '▐',
+                63 => '█',
+                _ => {
+                    let skipped = (mask > 21) as u32 + (mask > 42) as u32;
+                    char::from_u32(SEXTANT_FIRST + mask - 1 - skipped).unwrap()
+                },
+            },
+        }
+    }
+}
+
+/// Draws 2×2 or 2×3 pixels per cell with block characters. For every
+/// cell the character and the foreground/background pair with the least
+/// color error are chosen. In cells with transparent pixels the opaque
+/// ones are drawn with their average color over the default background
+#[derive(Debug, Clone, Default)]
+pub struct BlockEncoder {
+    symbols: BlockSymbols,
+    grid: CellGrid,
+}
+
+impl BlockEncoder {
+    pub fn new(symbols: BlockSymbols) -> BlockEncoder {
+        BlockEncoder {
+            symbols,
+            grid: CellGrid::default(),
+        }
+    }
+}
+
+impl Encoder for BlockEncoder {
+    fn resolution(&self) -> CellResolution {
+        CellResolution::new(2, self.symbols.rows())
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>> {
+        let rows_per_cell = self.symbols.rows();
+        let cols = (frame.width() / 2) as u16;
+        let rows = (frame.height() / rows_per_cell) as u16;
+        self.grid.resize(cols, rows);
+
+        let mut pixels = [[0i32; 3]; 6];
+        let count = 2 * rows_per_cell as usize;
+        let pixels = &mut pixels[..count];
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let mut opaque = 0;
+
+                for (i, pixel) in pixels.iter_mut().enumerate() {
+                    let x = col as u32 * 2 + i as u32 % 2;
+                    let y = row as u32 * rows_per_cell + i as u32 / 2;
+
+                    let rgba = frame.get_pixel(x, y);
+                    let [r, g, b, _] = rgba.0;
+                    *pixel = [r as i32, g as i32, b as i32];
+
+                    if !is_transparent(rgba) {
+                        opaque |= 1 << i;
+                    }
+                }
+
+                let full = (1 << count) - 1;
+
+                let cell = if opaque == full {
+                    let (mask, fg, bg) = best_split(pixels);
+
+                    Cell {
+                        glyph: self.symbols.glyph(mask),
+                        fg: (mask != 0).then_some(fg),
+                        bg: Some(bg),
+                    }
+                } else if opaque == 0 {
+                    Cell::default()
+                } else {
+                    Cell {
+                        glyph: self.symbols.glyph(opaque),
+                        fg: Some(average(pixels, opaque)),
+                        bg: None,
+                    }
+                };
+
+                self.grid.set(col, row, cell);
+            }
+        }
+
+        Ok(Encoded::Cells(&self.grid))
+    }
+}
+
+/// Average color of the pixels, set in the mask
+fn average(pixels: &[[i32; 3]], mask: u32) -> Rgb<u8> {
+    let mut sum = [0i32; 3];
+    let mut count = 0;
+
+    for (_, pixel) in pixels.iter().enumerate().filter(|(i, _)| mask >> i & 1 != 0) {
+        for c in 0..3 {
+            sum[c] += pixel[c];
+        }
+        count += 1;
+    }
+
+    Rgb(sum.map(|s| (s / count.max(1)) as u8))
+}
+
+/// Finds the split of pixels into foreground and background,
+/// which minimizes the squared error of their mean colors
+fn best_split(pixels: &[[i32; 3]]) -> (u32, Rgb<u8>, Rgb<u8>) {
+    let full = (1u32 << pixels.len()) - 1;
+
+    let mut best = (0, [0; 3], [0; 3]);
+    let mut best_error = i64::MAX;
+
+    // Complementary masks give the same split with swapped colors, so only
+    // masks without the last pixel are checked. Solid cells get mask 0
+    // and are drawn with the background color only
+    for mask in 0..=(full >> 1) {
+        let mut sums = [[0i32; 3]; 2];
+        let mut counts = [0i32; 2];
+
+        for (i, pixel) in pixels.iter().enumerate() {
+            let side = (mask >> i & 1) as usize;
+            counts[side] += 1;
+            for c in 0..3 {
+                sums[side][c] += pixel[c];
+            }
+        }
+
+        let means = [0, 1].map(|side| sums[side].map(|s| s / counts[side].max(1)));
+
+        let error = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| {
+                let mean = means[(mask >> i & 1) as usize];
+                (0..3).map(|c| ((pixel[c] - mean[c]) as i64).pow(2)).sum::<i64>()
+            })
+            .sum::<i64>();
+
+        if error < best_error {
+            best_error = error;
+            best = (mask, means[1], means[0]);
+        }
+    }
+
+    let (mask, fg, bg) = best;
+
+    (mask, Rgb(fg.map(|c| c as u8)), Rgb(bg.map(|c| c as u8)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quadrant_glyphs() {
+        assert_eq!(BlockSymbols::Quadrant.glyph(0b0000), ' ');
+        assert_eq!(BlockSymbols::Quadrant.glyph(0b0011), '▀');
+        assert_eq!(BlockSymbols::Quadrant.glyph(0b0101), '▌');
+        assert_eq!(BlockSymbols::Quadrant.glyph(0b1111), '█');
+    }
+
+    #[test]
+    fn sextant_glyphs() {
+        let glyph = |mask| BlockSymbols::Sextant.glyph(mask);
+
+        assert_eq!(glyph(0), ' ');
+        assert_eq!(glyph(1), '\u{1FB00}');
+        assert_eq!(glyph(20), '\u{1FB13}');
+        assert_eq!(glyph(21), '▌');
+        assert_eq!(glyph(22), '\u{1FB14}');
+        assert_eq!(glyph(42), '▐');
+        assert_eq!(glyph(43), '\u{1FB28}');
+        assert_eq!(glyph(62), '\u{1FB3B}');
+        assert_eq!(glyph(63), '█');
+    }
+
+    #[test]
+    fn sextant_glyphs_are_distinct() {
+        let mut glyphs = (0..64).map(|mask| BlockSymbols::Sextant.glyph(mask)).collect::<Vec<_>>();
+        glyphs.sort();
+        glyphs.dedup();
+
+        assert_eq!(glyphs.len(), 64);
+    }
+
+    #[test]
+    fn best_split_of_two_colors() {
+        let (black, white) = ([0, 0, 0], [255, 255, 255]);
+        let (mask, fg, bg) = best_split(&[white, black, white, black]);
+
+        // The last pixel is always in the background
+        assert_eq!(mask, 0b0101);
+        assert_eq!(fg, Rgb([255; 3]));
+        assert_eq!(bg, Rgb([0; 3]));
+    }
+}
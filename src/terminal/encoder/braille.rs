@@ -0,0 +1,122 @@
+use std::io;
+
+use image::Rgb;
+
+use crate::{terminal::cell::{Cell, CellGrid}, utils::CellResolution};
+
+use super::{is_transparent, luminance, Encoded, Encoder, Frame};
+
+/// First character of the Unicode Braille Patterns block
+pub(crate) const BRAILLE_BLANK: u32 = 0x2800;
+
+/// Dot bits of a Braille character, indexed by `[y][x]` of the pixel in a cell
+const DOTS: [[u32; 2]; 4] = [
+    [0x01, 0x08],
+    [0x02, 0x10],
+    [0x04, 0x20],
+    [0x40, 0x80],
+];
+
+/// Decides which pixels of a cell are drawn as Braille dots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrailleThreshold {
+    /// Pixels brighter than the value are drawn
+    Luminance(u8),
+    /// Pixels, which luminance differs from their right or bottom
+    /// neighbour by more than the value, are drawn. Suits wireframes
+    /// and filled shapes, which should be shown as outlines
+    Edge(u8),
+}
+
+impl Default for BrailleThreshold {
+    fn default() -> Self {
+        BrailleThreshold::Luminance(32)
+    }
+}
+
+/// Draws 2×4 pixels per cell with Braille characters. Foreground
+/// color of the cell is the average color of its drawn pixels
+#[derive(Debug, Clone, Default)]
+pub struct BrailleEncoder {
+    threshold: BrailleThreshold,
+    grid: CellGrid,
+}
+
+impl BrailleEncoder {
+    pub fn new(threshold: BrailleThreshold) -> BrailleEncoder {
+        BrailleEncoder {
+            threshold,
+            grid: CellGrid::default(),
+        }
+    }
+
+    /// Transparent pixels are never drawn
+    fn is_drawn(&self, frame: &Frame<'_>, x: u32, y: u32) -> bool {
+        if is_transparent(frame.get_pixel(x, y)) {
+            return false;
+        }
+
+        let pixel = luminance(frame.get_pixel(x, y));
+
+        match self.threshold {
+            BrailleThreshold::Luminance(threshold) => pixel > threshold,
+            BrailleThreshold::Edge(threshold) => {
+                let right = luminance(frame.get_pixel((x + 1).min(frame.width() - 1), y));
+                let bottom = luminance(frame.get_pixel(x, (y + 1).min(frame.height() - 1)));
+
+                pixel.abs_diff(right).max(pixel.abs_diff(bottom)) > threshold
+            },
+        }
+    }
+}
+
+impl Encoder for BrailleEncoder {
+    fn resolution(&self) -> CellResolution {
+        CellResolution::new(2, 4)
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>> {
+        let cols = (frame.width() / 2) as u16;
+        let rows = (frame.height() / 4) as u16;
+        self.grid.resize(cols, rows);
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let mut dots = 0;
+                let mut sum = [0u32; 3];
+                let mut count = 0;
+
+                for (dy, line) in DOTS.iter().enumerate() {
+                    for (dx, dot) in line.iter().enumerate() {
+                        let x = col as u32 * 2 + dx as u32;
+                        let y = row as u32 * 4 + dy as u32;
+
+                        if self.is_drawn(frame, x, y) {
+                            let pixel = frame.get_pixel(x, y);
+
+                            dots |= dot;
+                            count += 1;
+                            for (sum, channel) in sum.iter_mut().zip(pixel.0) {
+                                *sum += channel as u32;
+                            }
+                        }
+                    }
+                }
+
+                let cell = if count > 0 {
+                    Cell {
+                        glyph: char::from_u32(BRAILLE_BLANK + dots).unwrap(),
+                        fg: Some(Rgb(sum.map(|s| (s / count) as u8))),
+                        bg: None,
+                    }
+                } else {
+                    Cell::default()
+                };
+
+                self.grid.set(col, row, cell);
+            }
+        }
+
+        Ok(Encoded::Cells(&self.grid))
+    }
+}
@@ -0,0 +1,57 @@
+use std::io;
+
+use image::Rgb;
+
+use crate::{terminal::cell::{Cell, CellGrid}, utils::CellResolution};
+
+use super::{is_transparent, Encoded, Encoder, Frame};
+
+/// Draws two pixels per cell with the "▀" character, using
+/// the foreground color for the top pixel and the background
+/// color for the bottom one. Transparent pixels keep the default
+/// background, so a cell with only the bottom pixel is drawn with "▄"
+#[derive(Debug, Clone, Default)]
+pub struct HalfBlockEncoder {
+    grid: CellGrid,
+}
+
+impl Encoder for HalfBlockEncoder {
+    fn resolution(&self) -> CellResolution {
+        CellResolution::HALF_BLOCK
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>> {
+        let cols = frame.width() as u16;
+        let rows = (frame.height() / 2) as u16;
+        self.grid.resize(cols, rows);
+
+        for y in 0..rows {
+            for x in 0..cols {
+                let top = frame.get_pixel(x as u32, y as u32 * 2);
+                let bottom = frame.get_pixel(x as u32, y as u32 * 2 + 1);
+
+                let color = |pixel: &image::Rgba<u8>| {
+                    (!is_transparent(pixel)).then_some(Rgb([pixel.0[0], pixel.0[1], pixel.0[2]]))
+                };
+
+                let cell = match (color(top), color(bottom)) {
+                    (None, None) => Cell::default(),
+                    (None, Some(bottom)) => Cell {
+                        glyph: '▄',
+                        fg: Some(bottom),
+                        bg: None,
+                    },
+                    (top, bottom) => Cell {
+                        glyph: '▀',
+                        fg: top,
+                        bg: bottom,
+                    },
+                };
+
+                self.grid.set(x, y, cell);
+            }
+        }
+
+        Ok(Encoded::Cells(&self.grid))
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::BuildHasher,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::utils::CellResolution;
+
+use super::{Encoded, Encoder, Frame};
+
+/// Maximum size of a base64 payload in a single escape sequence
+const CHUNK_SIZE: usize = 4096;
+
+/// Placement, which is reused by every frame
+const PLACEMENT_ID: u32 = 1;
+
+/// Directory, in which POSIX shared memory objects are files on Linux
+const SHM_DIR: &str = "/dev/shm";
+
+/// Number of random names, tried before creating a file fails
+const CREATE_ATTEMPTS: usize = 16;
+
+/// Describes how image data reaches the terminal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KittyTransmission {
+    /// The fastest medium, which the terminal has read in a query, when
+    /// capabilities were probed. Pixels are sent through the escape
+    /// sequences, if the terminal hasn't been probed
+    #[default]
+    Auto,
+    /// Pixels are sent as base64 chunks through the escape sequences
+    Direct,
+    /// Pixels are written to a temporary file, which the terminal reads and deletes
+    TempFile,
+    /// Pixels are written to a POSIX shared memory object, which the terminal
+    /// reads and unlinks. Objects are created as files in `/dev/shm`, so it
+    /// is supported on Linux only, and temporary files are used elsewhere
+    SharedMemory,
+}
+
+impl KittyTransmission {
+    /// Resolves `Auto` transmission, which is not resolved
+    /// with probed capabilities, and unsupported shared memory
+    fn resolve(self) -> KittyTransmission {
+        match self {
+            KittyTransmission::Auto => KittyTransmission::Direct,
+            KittyTransmission::SharedMemory if !shared_memory_supported() => KittyTransmission::TempFile,
+            transmission => transmission,
+        }
+    }
+
+    /// Writes pixels to a new file or shared memory object and returns the
+    /// medium key and the payload of the transmission command with it
+    pub(crate) fn write(self, data: &[u8]) -> io::Result<(char, String)> {
+        match self {
+            KittyTransmission::TempFile => KittyEncoder::write_new_file(&std::env::temp_dir(), data)
+                .map(|path| ('t', path.to_string_lossy().into_owned())),
+            // Shared memory objects are named relative to the directory with a leading slash
+            KittyTransmission::SharedMemory => KittyEncoder::write_new_file(Path::new(SHM_DIR), data)
+                .map(|path| ('s', format!("/{}", path.file_name().unwrap_or_default().to_string_lossy()))),
+            _ => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// Path of the file, written by [`KittyTransmission::write`]
+    pub(crate) fn path(medium: char, name: &str) -> PathBuf {
+        match medium {
+            's' => Path::new(SHM_DIR).join(name.trim_start_matches('/')),
+            _ => PathBuf::from(name),
+        }
+    }
+}
+
+/// Whether POSIX shared memory objects can be created as files
+pub(crate) fn shared_memory_supported() -> bool {
+    cfg!(target_os = "linux") && Path::new(SHM_DIR).is_dir()
+}
+
+/// Encodes frames using the Kitty graphics protocol. Frames are
+/// transmitted as raw RGBA images and shown in the same placement,
+/// so each new frame replaces the previous one in place
+#[derive(Debug, Clone)]
+pub struct KittyEncoder {
+    cell_width: u32,
+    cell_height: u32,
+    transmission: KittyTransmission,
+    image_ids: [u32; 2],
+    current: usize,
+    shown: bool,
+    out: Vec<u8>,
+}
+
+impl KittyEncoder {
+    /// Creates a new encoder for a terminal, which cells
+    /// are `cell_width`×`cell_height` screen pixels
+    pub fn new(cell_width: u32, cell_height: u32, transmission: KittyTransmission) -> KittyEncoder {
+        // Image ids are shared by all programs in the terminal window,
+        // so they are derived from the process id to avoid collisions
+        let base = (std::process::id() & 0x3fff_ffff) << 1;
+
+        KittyEncoder {
+            cell_width: cell_width.max(1),
+            cell_height: cell_height.max(1),
+            transmission: transmission.resolve(),
+            image_ids: [base + 2, base + 3],
+            current: 0,
+            shown: false,
+            out: vec![],
+        }
+    }
+
+    /// Writes frame pixels to a new file in `dir` and returns its path. The terminal
+    /// deletes temporary files after reading only if their names contain
+    /// `tty-graphics-protocol`. The name is random and the file must not exist,
+    /// so another user can't make us write through a link, placed in advance
+    fn write_new_file(dir: &Path, data: &[u8]) -> io::Result<PathBuf> {
+        let random = RandomState::new();
+
+        for attempt in 0..CREATE_ATTEMPTS {
+            let nanos = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos());
+
+            let name = format!("tty-graphics-protocol-termgpu-{:016x}", random.hash_one((nanos, attempt)));
+            let path = dir.join(name);
+
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+            let mut file = match options.open(&path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+
+            if let Err(e) = file.write_all(data) {
+                let _ = fs::remove_file(&path);
+                return Err(e);
+            }
+
+            return Ok(path);
+        }
+
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, "Failed to find an unused file name"))
+    }
+
+    fn write_direct(out: &mut Vec<u8>, control: &str, data: &[u8]) -> io::Result<()> {
+        let payload = STANDARD.encode(data);
+        let mut chunks = payload.as_bytes().chunks(CHUNK_SIZE).peekable();
+
+        let mut first = true;
+        while let Some(chunk) = chunks.next() {
+            let more = chunks.peek().is_some() as u8;
+
+            if first {
+                write!(out, "\x1b_G{control},m={more};")?;
+                first = false;
+            } else {
+                write!(out, "\x1b_Gm={more};")?;
+            }
+
+            out.extend_from_slice(chunk);
+            out.extend_from_slice(b"\x1b\\");
+        }
+
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &Frame<'_>, out: &mut Vec<u8>) -> io::Result<()> {
+        if frame.width() == 0 || frame.height() == 0 {
+            return Ok(());
+        }
+
+        // New frame is uploaded as a separate image and then takes the place of the
+        // previous one, so the terminal never shows a partially transmitted frame
+        let image_id = self.image_ids[self.current];
+        let previous_id = self.image_ids[1 - self.current];
+
+        // q=2 suppresses terminal responses, which would otherwise end up in the input
+        let control = format!("a=t,f=32,s={},v={},i={image_id},q=2", frame.width(), frame.height());
+
+        let file = match self.transmission {
+            KittyTransmission::Direct => None,
+            transmission => Some(transmission.write(frame.as_raw())),
+        };
+
+        match file {
+            Some(Ok((medium, name))) => {
+                let name = STANDARD.encode(name.as_bytes());
+                write!(out, "\x1b_G{control},t={medium};{name}\x1b\\")?;
+            },
+            // The medium is not retried, so a failing one doesn't cost every frame
+            Some(Err(e)) => {
+                log::error!("Failed to write a Kitty frame with {:?}, falling back to direct transmission: {e}", self.transmission);
+
+                self.transmission = KittyTransmission::Direct;
+                KittyEncoder::write_direct(out, &control, frame.as_raw())?;
+            },
+            None => KittyEncoder::write_direct(out, &control, frame.as_raw())?,
+        }
+
+        // C=1 keeps the cursor in place, so the image never scrolls the screen
+        write!(out, "\x1b_Ga=p,i={image_id},p={PLACEMENT_ID},C=1,q=2\x1b\\")?;
+
+        if self.shown {
+            write!(out, "\x1b_Ga=d,d=I,i={previous_id},q=2\x1b\\")?;
+        }
+
+        self.shown = true;
+        self.current = 1 - self.current;
+
+        Ok(())
+    }
+}
+
+impl Encoder for KittyEncoder {
+    fn resolution(&self) -> CellResolution {
+        CellResolution::new(self.cell_width, self.cell_height)
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>> {
+        let mut out = std::mem::take(&mut self.out);
+        out.clear();
+
+        let result = self.write_frame(frame, &mut out);
+        self.out = out;
+        result?;
+
+        Ok(Encoded::Bytes(&self.out))
+    }
+
+    fn set_cell_pixels(&mut self, (cell_width, cell_height): (u32, u32)) {
+        self.cell_width = cell_width.max(1);
+        self.cell_height = cell_height.max(1);
+    }
+
+    /// Deletes both images, so neither stays on the screen
+    /// after the encoder is replaced
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+        if self.shown {
+            for image_id in self.image_ids {
+                write!(out, "\x1b_Ga=d,d=I,i={image_id},q=2\x1b\\")?;
+            }
+        }
+
+        self.shown = false;
+        self.current = 0;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(encoder: &mut KittyEncoder) -> String {
+        let data = [0; 4];
+        let frame = Frame::from_raw(1, 1, &data[..]).unwrap();
+
+        let Ok(Encoded::Bytes(bytes)) = encoder.encode(&frame) else { panic!("Expected bytes") };
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn direct_chunks() {
+        // 6147 bytes are 8196 base64 characters: two full chunks and four characters
+        let data: Vec<u8> = (0..6147).map(|i| i as u8).collect();
+        let mut out = vec![];
+        KittyEncoder::write_direct(&mut out, "a=t,i=5", &data).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let chunks: Vec<_> = out.split_terminator("\x1b\\").collect();
+        let payloads: Vec<_> = chunks.iter().map(|chunk| chunk.split_once(';').unwrap().1).collect();
+
+        assert_eq!(payloads.iter().map(|payload| payload.len()).collect::<Vec<_>>(), [4096, 4096, 4]);
+        assert_eq!(payloads.concat(), STANDARD.encode(&data));
+
+        assert!(chunks[0].starts_with("\x1b_Ga=t,i=5,m=1;"));
+        assert!(chunks[1].starts_with("\x1b_Gm=1;"));
+        assert!(chunks[2].starts_with("\x1b_Gm=0;"));
+
+        let mut out = vec![];
+        KittyEncoder::write_direct(&mut out, "a=t,i=5", &[0; 3]).unwrap();
+        assert_eq!(out, b"\x1b_Ga=t,i=5,m=0;AAAA\x1b\\");
+    }
+
+    #[test]
+    fn frames_alternate_images() {
+        let mut encoder = KittyEncoder::new(8, 16, KittyTransmission::Direct);
+        let [first, second] = encoder.image_ids;
+
+        let transmit = |id| format!("\x1b_Ga=t,f=32,s=1,v=1,i={id},q=2,m=0;AAAAAA==\x1b\\");
+        let place = |id| format!("\x1b_Ga=p,i={id},p=1,C=1,q=2\x1b\\");
+        let delete = |id| format!("\x1b_Ga=d,d=I,i={id},q=2\x1b\\");
+
+        assert_eq!(encode(&mut encoder), transmit(first) + &place(first));
+        assert_eq!(encode(&mut encoder), transmit(second) + &place(second) + &delete(first));
+        assert_eq!(encode(&mut encoder), transmit(first) + &place(first) + &delete(second));
+    }
+
+    #[test]
+    fn finish_deletes_shown_images() {
+        let mut encoder = KittyEncoder::new(8, 16, KittyTransmission::Direct);
+        let [first, second] = encoder.image_ids;
+
+        let mut out = vec![];
+        encoder.finish(&mut out).unwrap();
+        assert!(out.is_empty());
+
+        encode(&mut encoder);
+        encode(&mut encoder);
+        encoder.finish(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("\x1b_Ga=d,d=I,i={first},q=2\x1b\\\x1b_Ga=d,d=I,i={second},q=2\x1b\\"),
+        );
+
+        // The next frame starts over and has no previous image to delete
+        let encoded = encode(&mut encoder);
+        assert!(encoded.contains(&format!("i={first},")));
+        assert!(!encoded.contains("a=d"));
+    }
+}
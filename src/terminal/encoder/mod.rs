@@ -0,0 +1,116 @@
+//! Encoder module contains output modes, which turn the rendered
+//! canvas into escape sequences or character cells
+
+use std::io;
+
+use image::{ImageBuffer, Rgba};
+
+use crate::utils::CellResolution;
+
+use super::cell::CellGrid;
+
+pub mod ascii;
+pub mod blocks;
+pub mod braille;
+pub mod half_block;
+pub mod kitty;
+pub mod sixel;
+
+pub use ascii::{AsciiEncoder, DEFAULT_ASCII_RAMP};
+pub use blocks::{BlockEncoder, BlockSymbols};
+pub use braille::{BrailleEncoder, BrailleThreshold};
+pub use half_block::HalfBlockEncoder;
+pub use kitty::{KittyEncoder, KittyTransmission};
+pub use sixel::SixelEncoder;
+
+/// RGBA frame, read back from the renderer canvas
+pub type Frame<'a> = ImageBuffer<Rgba<u8>, &'a [u8]>;
+
+/// Describes how rendered frames are drawn in the terminal
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum OutputMode {
+    /// Each cell shows two vertically stacked pixels using the "▀" character
+    #[default]
+    HalfBlock,
+    /// Frames are sent as Sixel graphics at the real pixel resolution
+    /// of the terminal. Supported by xterm, foot, mlterm and others
+    Sixel,
+    /// Frames are sent as RGBA images using the Kitty graphics protocol
+    /// at the real pixel resolution. Supported by Kitty, WezTerm and others
+    Kitty(KittyTransmission),
+    /// Each cell shows 2×4 pixels as Braille dots of a single color
+    Braille(BrailleThreshold),
+    /// Each cell shows 2×2 or 2×3 pixels using quadrant or sextant characters
+    /// with the best fitting foreground and background colors
+    Blocks(BlockSymbols),
+    /// Each cell shows a character of the density ramp, picked by brightness.
+    /// Suits terminals and logs without color support
+    Ascii {
+        /// Characters ordered from the darkest to the brightest
+        ramp: String,
+        /// Whether cells are colored with the average color of their pixels
+        color: bool,
+    },
+}
+
+impl OutputMode {
+    /// Creates an encoder for the mode. `cell_pixels` is the size of
+    /// a single terminal cell in screen pixels
+    pub fn encoder(&self, cell_pixels: (u32, u32)) -> Box<dyn Encoder> {
+        match self {
+            OutputMode::HalfBlock => Box::new(HalfBlockEncoder::default()),
+            OutputMode::Sixel => Box::new(SixelEncoder::new(cell_pixels.0, cell_pixels.1)),
+            OutputMode::Kitty(transmission) => {
+                Box::new(KittyEncoder::new(cell_pixels.0, cell_pixels.1, *transmission))
+            },
+            OutputMode::Braille(threshold) => Box::new(BrailleEncoder::new(*threshold)),
+            OutputMode::Blocks(symbols) => Box::new(BlockEncoder::new(*symbols)),
+            OutputMode::Ascii { ramp, color } => Box::new(AsciiEncoder::new(ramp, *color)),
+        }
+    }
+}
+
+/// Encoded frame, which is ready to be written to the terminal
+pub enum Encoded<'a> {
+    /// Escape sequences, which draw the frame from the current cursor position
+    Bytes(&'a [u8]),
+    /// Character cells, which fill the screen starting from its top left corner
+    Cells(&'a CellGrid),
+}
+
+/// Turns canvas frames into data, written to the terminal
+pub trait Encoder: Send {
+    /// Number of canvas pixels drawn in a single terminal cell
+    fn resolution(&self) -> CellResolution;
+
+    /// Encodes the frame. The result is kept in the encoder
+    /// until the next call
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>>;
+
+    /// Changes the size of a terminal cell in screen pixels. Encoders,
+    /// which draw at the real pixel resolution, change their resolution
+    fn set_cell_pixels(&mut self, _cell_pixels: (u32, u32)) {}
+
+    /// Writes escape sequences, which remove images, the encoder has
+    /// placed over the cells. It is called before the encoder is
+    /// replaced and when the terminal leaves the alternate screen
+    fn finish(&mut self, _out: &mut Vec<u8>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Pixels with lower alpha are transparent: the terminal
+/// background is left showing through them
+pub const ALPHA_THRESHOLD: u8 = 128;
+
+/// Whether the terminal background should show through the pixel
+pub fn is_transparent(pixel: &Rgba<u8>) -> bool {
+    pixel.0[3] < ALPHA_THRESHOLD
+}
+
+/// Relative luminance (Rec. 601) of the pixel
+pub fn luminance(pixel: &Rgba<u8>) -> u8 {
+    let [r, g, b, _] = pixel.0;
+
+    ((299 * r as u32 + 587 * g as u32 + 114 * b as u32) / 1000) as u8
+}
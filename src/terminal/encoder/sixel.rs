@@ -0,0 +1,398 @@
+use std::io::{self, Write};
+
+use crate::utils::CellResolution;
+
+use super::{is_transparent, Encoded, Encoder, Frame};
+
+/// Maximum number of color registers used by a single image
+pub const SIXEL_PALETTE_SIZE: usize = 256;
+
+/// Number of bins in the RGB555 color histogram
+const HISTOGRAM_SIZE: usize = 1 << 15;
+
+/// Palette index of transparent pixels, which are not drawn
+const TRANSPARENT: u16 = u16::MAX;
+
+/// Encodes frames as Sixel graphics. Colors are quantized to an
+/// adaptive palette with median cut and pixel runs are compressed
+/// with run-length encoding. Transparent pixels are not drawn, and
+/// the cells under them are erased to the default background first
+#[derive(Debug, Clone)]
+pub struct SixelEncoder {
+    cell_width: u32,
+    cell_height: u32,
+    histogram: Vec<u32>,
+    lookup: Vec<u8>,
+    indices: Vec<u16>,
+    band: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl SixelEncoder {
+    /// Creates a new encoder for a terminal, which cells
+    /// are `cell_width`×`cell_height` screen pixels
+    pub fn new(cell_width: u32, cell_height: u32) -> SixelEncoder {
+        SixelEncoder {
+            cell_width: cell_width.max(1),
+            cell_height: cell_height.max(1),
+            histogram: vec![0; HISTOGRAM_SIZE],
+            lookup: vec![0; HISTOGRAM_SIZE],
+            indices: vec![],
+            band: vec![],
+            out: vec![],
+        }
+    }
+
+    /// Builds the palette for the frame and fills the lookup table,
+    /// which maps RGB555 colors to palette indices
+    fn quantize(&mut self, frame: &Frame<'_>) -> Vec<[u8; 3]> {
+        self.histogram.fill(0);
+        for pixel in frame.pixels().filter(|pixel| !is_transparent(pixel)) {
+            self.histogram[rgb555(pixel.0)] += 1;
+        }
+
+        let mut colors = self.histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(color, count)| (color as u16, *count))
+            .collect::<Vec<_>>();
+
+        let mut boxes = vec![ColorBox::new(0, colors.len(), &colors)];
+
+        while boxes.len() < SIXEL_PALETTE_SIZE {
+            let Some((i, _)) = boxes
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| b.len() > 1)
+                .max_by_key(|(_, b)| b.score())
+            else { break };
+
+            let color_box = boxes.swap_remove(i);
+            let (left, right) = color_box.split(&mut colors);
+            boxes.push(left);
+            boxes.push(right);
+        }
+
+        boxes
+            .iter()
+            .enumerate()
+            .map(|(i, color_box)| {
+                for (color, _) in &colors[color_box.start..color_box.end] {
+                    self.lookup[*color as usize] = i as u8;
+                }
+
+                color_box.average(&colors)
+            })
+            .collect()
+    }
+
+    fn write_frame(&mut self, frame: &Frame<'_>, out: &mut Vec<u8>) -> io::Result<()> {
+        let width = frame.width() as usize;
+        let height = frame.height() as usize;
+
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let palette = self.quantize(frame);
+
+        self.indices.clear();
+        self.indices.extend(frame.pixels().map(|pixel| {
+            if is_transparent(pixel) { TRANSPARENT } else { self.lookup[rgb555(pixel.0)] as u16 }
+        }));
+
+        // Transparent pixels keep what is under them, so the cells
+        // of the previous frame are erased first
+        if self.indices.contains(&TRANSPARENT) {
+            let cols = width.div_ceil(self.cell_width as usize);
+            let rows = height.div_ceil(self.cell_height as usize);
+
+            out.extend_from_slice(b"\x1b7");
+            for row in 0..rows {
+                if row > 0 {
+                    out.extend_from_slice(b"\x1b[B");
+                }
+                write!(out, "\x1b[{cols}X")?;
+            }
+            out.extend_from_slice(b"\x1b8");
+        }
+
+        // DCS with 1:1 pixel aspect ratio, transparent background
+        // and the image size in raster attributes
+        write!(out, "\x1bP0;1;0q\"1;1;{width};{height}")?;
+
+        for (i, [r, g, b]) in palette.iter().enumerate() {
+            write!(out, "#{i};2;{};{};{}", percent(*r), percent(*g), percent(*b))?;
+        }
+
+        let mut used = [false; SIXEL_PALETTE_SIZE];
+        self.band.resize(palette.len() * width, 0);
+
+        for top in (0..height).step_by(6) {
+            let band_height = (height - top).min(6);
+
+            used.fill(false);
+            self.band.fill(0);
+
+            for dy in 0..band_height {
+                let row = (top + dy) * width;
+
+                for x in 0..width {
+                    let color = self.indices[row + x];
+                    if color == TRANSPARENT {
+                        continue;
+                    }
+
+                    let color = color as usize;
+                    used[color] = true;
+                    self.band[color * width + x] |= 1 << dy;
+                }
+            }
+
+            let mut first = true;
+            for (color, _) in used.iter().enumerate().filter(|(_, used)| **used) {
+                if !first {
+                    // Graphics carriage return: overprint the same band with the next color
+                    out.push(b'$');
+                }
+                first = false;
+
+                write!(out, "#{color}")?;
+
+                let bits = &self.band[color * width..(color + 1) * width];
+                let len = bits.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+
+                let mut x = 0;
+                while x < len {
+                    let run = bits[x..len].iter().take_while(|b| **b == bits[x]).count();
+                    write_run(out, bits[x] + 0x3f, run)?;
+                    x += run;
+                }
+            }
+
+            // Graphics new line: move to the next band
+            out.push(b'-');
+        }
+
+        out.extend_from_slice(b"\x1b\\");
+
+        Ok(())
+    }
+}
+
+impl Encoder for SixelEncoder {
+    fn resolution(&self) -> CellResolution {
+        CellResolution::new(self.cell_width, self.cell_height)
+    }
+
+    fn set_cell_pixels(&mut self, (cell_width, cell_height): (u32, u32)) {
+        self.cell_width = cell_width.max(1);
+        self.cell_height = cell_height.max(1);
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> io::Result<Encoded<'_>> {
+        let mut out = std::mem::take(&mut self.out);
+        out.clear();
+
+        let result = self.write_frame(frame, &mut out);
+        self.out = out;
+        result?;
+
+        Ok(Encoded::Bytes(&self.out))
+    }
+}
+
+/// A range of histogram colors, sharing the same palette entry
+#[derive(Debug, Clone, Copy)]
+struct ColorBox {
+    start: usize,
+    end: usize,
+    population: u64,
+    channel: usize,
+    range: u8,
+}
+
+impl ColorBox {
+    fn new(start: usize, end: usize, colors: &[(u16, u32)]) -> ColorBox {
+        let mut min = [u8::MAX; 3];
+        let mut max = [u8::MIN; 3];
+        let mut population = 0;
+
+        for (color, count) in &colors[start..end] {
+            let channels = channels555(*color);
+            for c in 0..3 {
+                min[c] = min[c].min(channels[c]);
+                max[c] = max[c].max(channels[c]);
+            }
+            population += *count as u64;
+        }
+
+        let (channel, range) = (0..3)
+            .map(|c| (c, max[c].saturating_sub(min[c])))
+            .max_by_key(|(_, range)| *range)
+            .unwrap();
+
+        ColorBox { start, end, population, channel, range }
+    }
+
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Boxes with wide color ranges and many pixels are split first
+    fn score(&self) -> u64 {
+        self.range as u64 * self.population
+    }
+
+    /// Splits the box at the population median along its widest channel
+    fn split(&self, colors: &mut [(u16, u32)]) -> (ColorBox, ColorBox) {
+        let slice = &mut colors[self.start..self.end];
+        slice.sort_unstable_by_key(|(color, _)| channels555(*color)[self.channel]);
+
+        let mut accumulated = 0;
+        let mut median = slice.len() - 1;
+        for (i, (_, count)) in slice.iter().enumerate() {
+            accumulated += *count as u64;
+            if accumulated * 2 >= self.population {
+                median = i;
+                break;
+            }
+        }
+
+        let middle = self.start + (median + 1).min(slice.len() - 1);
+
+        (
+            ColorBox::new(self.start, middle, colors),
+            ColorBox::new(middle, self.end, colors),
+        )
+    }
+
+    /// Population-weighted average color of the box
+    fn average(&self, colors: &[(u16, u32)]) -> [u8; 3] {
+        let mut sum = [0u64; 3];
+
+        for (color, count) in &colors[self.start..self.end] {
+            let channels = channels555(*color);
+            for c in 0..3 {
+                sum[c] += expand5(channels[c]) as u64 * *count as u64;
+            }
+        }
+
+        sum.map(|s| (s / self.population.max(1)) as u8)
+    }
+}
+
+fn rgb555([r, g, b, _]: [u8; 4]) -> usize {
+    ((r as usize >> 3) << 10) | ((g as usize >> 3) << 5) | (b as usize >> 3)
+}
+
+fn channels555(color: u16) -> [u8; 3] {
+    [(color >> 10) as u8 & 0x1f, (color >> 5) as u8 & 0x1f, color as u8 & 0x1f]
+}
+
+fn expand5(value: u8) -> u8 {
+    (value << 3) | (value >> 2)
+}
+
+/// Sixel color registers use percents instead of bytes
+fn percent(value: u8) -> u32 {
+    (value as u32 * 100 + 127) / 255
+}
+
+fn write_run(out: &mut Vec<u8>, sixel: u8, run: usize) -> io::Result<()> {
+    if run > 3 {
+        write!(out, "!{run}")?;
+        out.push(sixel);
+    } else {
+        out.extend(std::iter::repeat_n(sixel, run));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(width: u32, height: u32, pixels: &[[u8; 4]]) -> Vec<u8> {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        pixels.concat()
+    }
+
+    fn encode(width: u32, height: u32, data: &[u8]) -> String {
+        let frame = Frame::from_raw(width, height, data).unwrap();
+        let mut encoder = SixelEncoder::new(8, 16);
+
+        let Ok(Encoded::Bytes(bytes)) = encoder.encode(&frame) else { panic!("Expected bytes") };
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn write_run_compresses_long_runs() {
+        let mut out = vec![];
+        write_run(&mut out, b'?', 3).unwrap();
+        write_run(&mut out, b'~', 4).unwrap();
+
+        assert_eq!(out, b"???!4~");
+    }
+
+    #[test]
+    fn single_color_frame() {
+        let data = frame(8, 1, &[[255, 0, 0, 255]; 8]);
+
+        // The only pixel row is the lowest bit of the sixel
+        assert_eq!(encode(8, 1, &data), "\x1bP0;1;0q\"1;1;8;1#0;2;100;0;0#0!8@-\x1b\\");
+    }
+
+    #[test]
+    fn colors_overprint_the_band() {
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let data = frame(2, 2, &[red, blue, blue, blue]);
+
+        let encoded = encode(2, 2, &data);
+        let bands = encoded.split_once("#1;2;").unwrap().1;
+
+        // Both colors are drawn in the same band, separated by the graphics carriage return
+        assert_eq!(bands.matches('$').count(), 1);
+        assert!(encoded.contains("0;0;100"));
+        assert!(encoded.contains("100;0;0"));
+    }
+
+    #[test]
+    fn transparent_pixels_are_not_drawn() {
+        let data = frame(2, 1, &[[0, 0, 0, 0], [255, 255, 255, 255]]);
+        let encoded = encode(2, 1, &data);
+
+        // Cells are erased, and the transparent pixel is an empty sixel
+        assert!(encoded.starts_with("\x1b7\x1b[1X\x1b8"));
+        assert!(encoded.contains("#0?@-"));
+    }
+
+    #[test]
+    fn median_cut_keeps_exact_colors() {
+        let colors = [[0, 0, 0, 255], [255, 255, 255, 255], [255, 0, 0, 255], [0, 255, 0, 255]];
+        let data = frame(4, 1, &colors);
+        let frame = Frame::from_raw(4, 1, &data[..]).unwrap();
+
+        let mut encoder = SixelEncoder::new(8, 16);
+        let mut palette = encoder.quantize(&frame);
+        palette.sort();
+
+        assert_eq!(palette, [[0, 0, 0], [0, 255, 0], [255, 0, 0], [255, 255, 255]]);
+    }
+
+    #[test]
+    fn median_cut_limits_palette() {
+        let pixels = (0..64 * 64)
+            .map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 128, 255])
+            .collect::<Vec<_>>();
+
+        let data = frame(64, 64, &pixels);
+        let frame = Frame::from_raw(64, 64, &data[..]).unwrap();
+
+        let mut encoder = SixelEncoder::new(8, 16);
+        assert_eq!(encoder.quantize(&frame).len(), SIXEL_PALETTE_SIZE);
+    }
+}
@@ -0,0 +1,967 @@
+use std::{
+    io::{self, stdout, IsTerminal, Stdout, Write},
+    sync::{atomic::{AtomicBool, Ordering}, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{event::input::{Input, KeyboardInput, MouseInput}, fatal, utils::{CellPixels, CellResolution, Size, Viewport}};
+use crossterm::{cursor::MoveTo, event as ctevent, terminal::{Clear, ClearType}, QueueableCommand};
+use capabilities::{ProbeRequests, TerminalCapabilities};
+use cell::{write_cells, write_cells_diff, CellGrid};
+use color::{ColorDepth, Dithering, Quantizer};
+use encoder::{Encoded, Encoder, Frame, KittyTransmission, OutputMode};
+use recorder::AsciicastRecorder;
+use theme::TerminalTheme;
+
+pub mod capabilities;
+pub mod cell;
+pub mod color;
+pub mod encoder;
+mod query;
+pub mod recorder;
+pub mod theme;
+
+pub mod prelude {
+    pub use super::{ScreenMode, Terminal};
+    pub use super::capabilities::TerminalCapabilities;
+    pub use super::color::{ColorDepth, Dithering};
+    pub use super::encoder::{BlockSymbols, BrailleThreshold, KittyTransmission, OutputMode};
+    pub use super::recorder::AsciicastRecorder;
+    pub use super::theme::TerminalTheme;
+}
+
+/// Cell size in screen pixels, assumed when the terminal doesn't report it
+pub const DEFAULT_CELL_PIXELS: (u32, u32) = (8, 16);
+
+/// Begin synchronized update (DEC mode 2026)
+const BEGIN_SYNC_UPDATE: &[u8] = b"\x1b[?2026h";
+
+/// End synchronized update (DEC mode 2026)
+const END_SYNC_UPDATE: &[u8] = b"\x1b[?2026l";
+
+/// Whether raw mode is enabled by a terminal
+static TTY_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the alternate screen is entered by a terminal
+static ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether mouse capture is enabled by a terminal
+static MOUSE_CAPTURE: AtomicBool = AtomicBool::new(false);
+
+/// Whether keyboard enhancement flags are pushed by a terminal
+static KEYBOARD_ENHANCEMENT: AtomicBool = AtomicBool::new(false);
+
+/// Where [`restore_tty`] writes, registered by [`Terminal::enable`]
+static RESTORE_OUT: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+/// Opens the output, which the TTY state is restored through
+type RestoreOut = fn() -> io::Result<Box<dyn Write + Send>>;
+
+/// Restores the TTY state, changed by [`Terminal::enable`]. It is
+/// called by [`fatal!`](crate::fatal) before the process exits.
+/// Escape sequences are written to the TTY of the enabled terminal
+#[doc(hidden)]
+pub fn restore_tty() {
+    let out = RESTORE_OUT.lock().unwrap_or_else(PoisonError::into_inner).take();
+    let Some(mut out) = out else { return };
+
+    // Flags are pushed on the alternate screen, so they are popped before leaving it
+    if KEYBOARD_ENHANCEMENT.swap(false, Ordering::SeqCst) {
+        let _ = crossterm::execute!(out, crossterm::event::PopKeyboardEnhancementFlags);
+    }
+
+    if MOUSE_CAPTURE.swap(false, Ordering::SeqCst) {
+        let _ = crossterm::execute!(out, crossterm::event::DisableMouseCapture);
+    }
+
+    if ALTERNATE_SCREEN.swap(false, Ordering::SeqCst) {
+        let _ = crossterm::execute!(out, crossterm::terminal::LeaveAlternateScreen);
+    }
+
+    if TTY_ENABLED.swap(false, Ordering::SeqCst) {
+        let _ = crossterm::execute!(out, crossterm::cursor::Show);
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+}
+
+/// Standard output, which the default terminal writes to
+fn restore_stdout() -> io::Result<Box<dyn Write + Send>> {
+    Ok(Box::new(stdout()))
+}
+
+/// Controlling terminal of the process, which raw mode is enabled on
+fn restore_controlling_tty() -> io::Result<Box<dyn Write + Send>> {
+    let path = if cfg!(windows) { "CONOUT$" } else { "/dev/tty" };
+
+    Ok(Box::new(std::fs::OpenOptions::new().write(true).open(path)?))
+}
+
+/// Where the terminal draws frames
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScreenMode {
+    /// Frames fill the alternate screen, and the shell
+    /// output is restored, when the terminal is disabled
+    #[default]
+    AlternateScreen,
+    /// Frames are drawn in the given number of rows below the cursor.
+    /// The last frame stays in the scrollback, when the terminal is disabled
+    Inline(u16),
+}
+
+/// Terminal, which draws frames into the sink. By default the sink
+/// is the standard output, but frames can be written to any
+/// [`Write`] implementation: a file, a socket or a buffer
+pub struct Terminal<W: Write = Stdout> {
+    cols: u16,
+    rows: u16,
+    sink: W,
+    is_tty: bool,
+    restore_out: RestoreOut,
+    capabilities: TerminalCapabilities,
+    theme: TerminalTheme,
+    output_mode: OutputMode,
+    encoder: Box<dyn Encoder>,
+    quantizer: Quantizer,
+    cells: CellGrid,
+    previous: Option<CellGrid>,
+    full_frame_len: usize,
+    synchronized_output: bool,
+    mouse_capture: bool,
+    frame: Vec<u8>,
+    recorder: Option<AsciicastRecorder>,
+    screen_mode: ScreenMode,
+    origin: (u16, u16),
+    screen: (u16, u16),
+    viewport: Option<Viewport>,
+    cell_pixels: CellPixels,
+}
+
+impl Terminal {
+    /// Creates a new terminal with the specified number of columns and rows.
+    pub fn new(cols: u16, rows: u16) -> Terminal {
+        Terminal::new_with_mode(cols, rows, OutputMode::default())
+    }
+
+    /// Creates a new terminal with the specified number of columns and rows,
+    /// which draws frames using the given output mode.
+    pub fn new_with_mode(cols: u16, rows: u16, output_mode: OutputMode) -> Terminal {
+        let is_tty = stdout().is_terminal();
+
+        Terminal::new_inner(cols, rows, stdout(), is_tty, restore_stdout, output_mode)
+    }
+}
+
+impl<W: Write> Terminal<W> {
+    /// Creates a new terminal with the specified number of columns and rows,
+    /// which writes frames to the sink. The sink is never treated as a TTY,
+    /// so raw mode and the alternate screen are not touched. See
+    /// [`Terminal::new_with_tty_sink`] for sinks, which may be a TTY
+    pub fn new_with_sink(cols: u16, rows: u16, sink: W, output_mode: OutputMode) -> Terminal<W> {
+        Terminal::new_inner(cols, rows, sink, false, restore_controlling_tty, output_mode)
+    }
+
+    /// Creates a new terminal with the specified number of columns and rows,
+    /// which writes frames to the sink, detecting whether it is a TTY. Raw mode,
+    /// input, query replies and the restore by [`fatal!`](crate::fatal) go through
+    /// the controlling terminal of the process, so the sink should be that
+    /// terminal, e.g. `/dev/tty` or stderr
+    pub fn new_with_tty_sink(cols: u16, rows: u16, sink: W, output_mode: OutputMode) -> Terminal<W>
+    where
+        W: IsTerminal,
+    {
+        let is_tty = sink.is_terminal();
+
+        Terminal::new_inner(cols, rows, sink, is_tty, restore_controlling_tty, output_mode)
+    }
+
+    fn new_inner(cols: u16, rows: u16, sink: W, is_tty: bool, restore_out: RestoreOut, output_mode: OutputMode) -> Terminal<W> {
+        let mut terminal = Terminal {
+            cols,
+            rows,
+            sink,
+            is_tty,
+            restore_out,
+            capabilities: TerminalCapabilities::from_env(),
+            theme: TerminalTheme::default(),
+            encoder: output_mode.encoder(DEFAULT_CELL_PIXELS),
+            output_mode,
+            quantizer: Quantizer::default(),
+            cells: CellGrid::default(),
+            previous: None,
+            full_frame_len: 0,
+            synchronized_output: false,
+            mouse_capture: true,
+            frame: vec![],
+            recorder: None,
+            screen_mode: ScreenMode::default(),
+            origin: (0, 0),
+            screen: (cols, rows),
+            viewport: None,
+            cell_pixels: CellPixels::DEFAULT,
+        };
+
+        terminal.update_area();
+        terminal
+    }
+
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+
+    /// Consumes the terminal and returns its sink
+    pub fn into_sink(self) -> W {
+        self.sink
+    }
+
+    /// Whether the sink is a real terminal
+    pub fn is_tty(&self) -> bool {
+        self.is_tty
+    }
+
+    /// Cell size in screen pixels. It is reported by the TTY (`ws_xpixel` and
+    /// `ws_ypixel` of `TIOCGWINSZ`) or in reply to CSI 16t, when capabilities
+    /// are probed. If neither is available, [`DEFAULT_CELL_PIXELS`] is used
+    pub fn cell_pixels(&self) -> CellPixels {
+        self.cell_pixels
+    }
+
+    /// Number of canvas pixels per cell in the current output mode. Sizes
+    /// of the terminal are converted to the renderer size with it
+    pub fn cell_resolution(&self) -> CellResolution {
+        self.encoder.resolution()
+    }
+
+    pub fn capabilities(&self) -> &TerminalCapabilities {
+        &self.capabilities
+    }
+
+    /// Queries the terminal for its capabilities, waiting for
+    /// replies no longer than `timeout`. The encoder is updated
+    /// with the reported cell size. The theme colors are queried
+    /// in the same batch, see [`Terminal::query_theme`]. Sinks, which
+    /// are not a TTY, get capabilities from the environment only
+    pub fn probe_capabilities(&mut self, timeout: Duration) -> &TerminalCapabilities {
+        if self.is_tty {
+            // Theme colors are queried in the same batch, so
+            // there is a single round-trip to the terminal. Files of the
+            // Kitty queries are removed, when the probe requests are dropped
+            let probe = ProbeRequests::new();
+            let requests = [probe.as_str(), &TerminalTheme::requests()].concat();
+            let received = query::query(&mut self.sink, &requests, timeout);
+
+            self.capabilities = TerminalCapabilities::from_replies(&received);
+            self.set_theme(TerminalTheme::from_replies(&received));
+        } else {
+            self.capabilities = TerminalCapabilities::from_env();
+        }
+
+        self.synchronized_output = self.capabilities.synchronized_output;
+        self.update_encoder();
+
+        // `Auto` Kitty transmission depends on the probed media
+        if self.output_mode == OutputMode::Kitty(KittyTransmission::Auto) {
+            self.replace_encoder();
+        }
+
+        &self.capabilities
+    }
+
+    pub fn theme(&self) -> &TerminalTheme {
+        &self.theme
+    }
+
+    /// Queries default colors and the 16-color palette of the terminal and
+    /// waits for the replies no longer than `timeout`. Reported palette
+    /// colors replace the assumed ones in the quantizer. Nothing is
+    /// queried, if the sink is not a TTY. The theme is already queried
+    /// by [`Terminal::probe_capabilities`], so this is only needed,
+    /// when the theme changes
+    pub fn query_theme(&mut self, timeout: Duration) -> &TerminalTheme {
+        if self.is_tty {
+            let theme = TerminalTheme::query(&mut self.sink, timeout);
+            self.set_theme(theme);
+        }
+
+        &self.theme
+    }
+
+    fn set_theme(&mut self, theme: TerminalTheme) {
+        self.theme = theme;
+
+        let palette = self.theme.palette_or(self.quantizer.palette());
+        self.quantizer.set_palette(palette);
+    }
+
+    pub fn synchronized_output(&self) -> bool {
+        self.synchronized_output
+    }
+
+    /// Enables wrapping of every frame in a synchronized update, so the
+    /// terminal shows it at once without tearing. It is enabled by
+    /// [`Terminal::probe_capabilities`], if the terminal supports it
+    pub fn set_synchronized_output(&mut self, enabled: bool) {
+        self.synchronized_output = enabled;
+    }
+
+    /// Starts recording of the emitted frames and input to an asciicast v2
+    /// `.cast` file. Kitty graphics, transmitted through files or shared memory,
+    /// can't be played back, so [`KittyTransmission::Direct`](encoder::KittyTransmission::Direct)
+    /// must be used with recording. In inline mode, the recording covers the
+    /// screen down to the reserved rows, as frames are drawn at their screen rows
+    pub fn start_recording(&mut self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        let Size::Terminal(cols, rows) = self.recorded_size() else { unreachable!() };
+        let recorder = AsciicastRecorder::create(path, cols, rows)?;
+
+        self.start_recording_with(recorder)
+    }
+
+    /// Starts recording with the given recorder, which should have the
+    /// [`Terminal::recorded_size`]. The next frame is drawn in full,
+    /// so the recording doesn't depend on the previous output
+    pub fn start_recording_with(&mut self, mut recorder: AsciicastRecorder) -> io::Result<()> {
+        recorder.output(b"\x1b[?25l\x1b[2J")?;
+
+        self.recorder = Some(recorder);
+        self.previous = None;
+
+        Ok(())
+    }
+
+    /// Stops recording and returns the recorder, if there is any
+    pub fn stop_recording(&mut self) -> Option<AsciicastRecorder> {
+        let mut recorder = self.recorder.take()?;
+
+        if let Err(e) = recorder.flush() {
+            log::error!("Failed to flush recording: {e}");
+        }
+
+        Some(recorder)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    /// Size of the recorded screen. In inline mode, it reaches from the top
+    /// of the screen to the last reserved row, so cursor moves of frames
+    /// land on the same rows, when the recording is played
+    pub fn recorded_size(&self) -> Size {
+        Size::Terminal(self.screen.0, self.origin.1.saturating_add(self.screen.1))
+    }
+
+    /// Records the recorded screen size, if it differs from the previous one
+    fn record_resize(&mut self, previous: Size) {
+        let Size::Terminal(cols, rows) = self.recorded_size() else { unreachable!() };
+
+        if Size::Terminal(cols, rows) != previous {
+            record(&mut self.recorder, |recorder| recorder.resize(cols, rows));
+        }
+    }
+
+    /// Records input, which doesn't come from [`Terminal::input`]
+    pub fn record_input(&mut self, input: &KeyboardInput) {
+        record(&mut self.recorder, |recorder| recorder.input(input));
+    }
+
+    pub fn screen_mode(&self) -> ScreenMode {
+        self.screen_mode
+    }
+
+    /// Changes where frames are drawn. It must be called before
+    /// [`Terminal::enable`]. Inline mode resizes the terminal
+    /// to the number of reserved rows
+    pub fn set_screen_mode(&mut self, screen_mode: ScreenMode) {
+        self.screen_mode = screen_mode;
+
+        if let ScreenMode::Inline(rows) = screen_mode {
+            self.resize_with(Size::new_terminal(self.screen.0, rows.max(1)));
+        }
+    }
+
+    pub fn viewport(&self) -> Option<Viewport> {
+        self.viewport
+    }
+
+    /// Limits drawing to the rectangle of cells, or restores drawing to the whole
+    /// screen if `None`. The viewport is clamped to the screen, and [`Terminal::size`]
+    /// describes it afterwards, so the renderer must be resized. In inline mode,
+    /// the viewport is placed relative to the reserved rows
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+        self.update_area();
+    }
+
+    /// Updates the size of the area, in which frames are drawn
+    fn update_area(&mut self) {
+        let (cols, rows) = self.screen;
+        self.viewport = self.viewport.map(|viewport| viewport.clamp(cols, rows));
+
+        (self.cols, self.rows) = self.viewport.map_or(self.screen, |viewport| (viewport.cols, viewport.rows));
+
+        // Sixel images, which reach the last row, scroll the screen
+        // by a line in xterm and mlterm, so the row is left empty
+        let top = self.viewport.map_or(0, |viewport| viewport.y);
+        if self.output_mode == OutputMode::Sixel && top + self.rows >= rows && self.rows > 1 {
+            self.rows -= 1;
+        }
+
+        self.update_encoder();
+    }
+
+    /// Screen position of the top left cell of the drawn area
+    fn offset(&self) -> (u16, u16) {
+        let (x, y) = self.viewport.map_or((0, 0), |viewport| (viewport.x, viewport.y));
+
+        (self.origin.0 + x, self.origin.1 + y)
+    }
+
+    pub fn output_mode(&self) -> &OutputMode {
+        &self.output_mode
+    }
+
+    /// Changes the output mode. The [`Terminal::cell_resolution`] is changed
+    /// as well, so the renderer must be resized afterwards. Sixel graphics
+    /// never cover the last row of the screen, so the size may change too
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+        self.replace_encoder();
+        self.update_area();
+    }
+
+    /// Sets the number of colors, used by text-based output modes.
+    /// Sixel and Kitty graphics are not affected
+    pub fn set_color_depth(&mut self, depth: ColorDepth) {
+        self.quantizer.set_depth(depth);
+        self.previous = None;
+    }
+
+    pub fn color_depth(&self) -> ColorDepth {
+        self.quantizer.depth()
+    }
+
+    /// Sets dithering, applied when color depth is lower than truecolor
+    pub fn set_dithering(&mut self, dithering: Dithering) {
+        self.quantizer.set_dithering(dithering);
+    }
+
+    pub fn dithering(&self) -> Dithering {
+        self.quantizer.dithering()
+    }
+
+    /// Updates the encoder with the current cell pixel size. The encoder is
+    /// kept, so images, it has shown, are replaced by its next frame
+    fn update_encoder(&mut self) {
+        let cell_pixels = self.is_tty
+            .then(crossterm::terminal::window_size)
+            .and_then(Result::ok)
+            .filter(|size| size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0)
+            .map(|size| (
+                size.width as u32 / size.columns as u32, 
+                size.height as u32 / size.rows as u32,
+            ))
+            .or(self.capabilities.cell_pixels)
+            .unwrap_or(DEFAULT_CELL_PIXELS);
+
+        self.cell_pixels = CellPixels::new(cell_pixels.0, cell_pixels.1);
+        self.encoder.set_cell_pixels(cell_pixels);
+        self.previous = None;
+    }
+
+    /// Replaces the encoder with a new one for the output mode. `Auto`
+    /// Kitty transmission is resolved with the probed capabilities
+    fn replace_encoder(&mut self) {
+        self.finish_encoder();
+
+        let output_mode = match self.output_mode {
+            OutputMode::Kitty(KittyTransmission::Auto) => OutputMode::Kitty(self.capabilities.kitty_transmission()),
+            ref output_mode => output_mode.clone(),
+        };
+
+        self.encoder = output_mode.encoder((self.cell_pixels.width, self.cell_pixels.height));
+    }
+
+    /// Removes images, which the encoder has placed over the cells
+    fn finish_encoder(&mut self) {
+        let mut out = vec![];
+        self.encoder.finish(&mut out).unwrap_or_else(|e| fatal!("Failed to remove images: {e}"));
+
+        if out.is_empty() {
+            return;
+        }
+
+        self.sink.write_all(&out)
+            .and_then(|_| self.sink.flush())
+            .unwrap_or_else(|e| fatal!("Failed to remove images: {e}"));
+
+        record(&mut self.recorder, |recorder| recorder.output(&out));
+    }
+
+    /// Quantizes cells and writes the ones, which have changed since the previous
+    /// frame. The whole grid is written if there is no previous frame or if the
+    /// difference takes more bytes than the last full frame
+    fn write_cells(&mut self) -> io::Result<()> {
+        self.quantizer.quantize(&mut self.cells);
+
+        let previous = self.previous
+            .as_ref()
+            .filter(|previous| previous.cols() == self.cells.cols() && previous.rows() == self.cells.rows());
+
+        let start = self.frame.len();
+        let mut full = true;
+
+        if let Some(previous) = previous {
+            write_cells_diff(previous, &self.cells, &self.quantizer, self.offset(), &mut self.frame)?;
+
+            full = self.frame.len() - start >= self.full_frame_len;
+            if full {
+                self.frame.truncate(start);
+            }
+        }
+
+        if full {
+            write_cells(&self.cells, &self.quantizer, self.offset(), &mut self.frame)?;
+            self.full_frame_len = self.frame.len() - start;
+        }
+
+        // Current cells become the previous frame, and the old
+        // allocation is reused for the next one
+        let previous = self.previous.replace(std::mem::take(&mut self.cells));
+        self.cells = previous.unwrap_or_default();
+
+        Ok(())
+    }
+
+    /// Enables raw mode and enters the alternate screen.
+    /// Does nothing if the sink is not a TTY
+    pub fn enable(&mut self) {
+        self.previous = None;
+
+        if !self.is_tty {
+            return;
+        }
+
+        // The TTY is restored through its own output, if the process
+        // exits with `fatal!`, while the sink is owned by the terminal
+        let restore_out = (self.restore_out)()
+            .unwrap_or_else(|e| fatal!("Failed to open the terminal: {e}"));
+
+        *RESTORE_OUT.lock().unwrap_or_else(PoisonError::into_inner) = Some(restore_out);
+
+        crossterm::terminal::enable_raw_mode()
+            .unwrap_or_else(|e| fatal!("Failed to enable raw mode: {e}"));
+
+        TTY_ENABLED.store(true, Ordering::SeqCst);
+
+        if self.mouse_capture {
+            crossterm::execute!(self.sink, crossterm::event::EnableMouseCapture)
+                .unwrap_or_else(|e| fatal!("Failed to enable mouse capture: {e}"));
+
+            MOUSE_CAPTURE.store(true, Ordering::SeqCst);
+        }
+
+        match self.screen_mode {
+            ScreenMode::AlternateScreen => {
+                crossterm::execute!(
+                    self.sink,
+                    crossterm::terminal::EnterAlternateScreen,
+                    crossterm::cursor::Hide,
+                )
+                .unwrap_or_else(|e| fatal!("Failed to clear terminal: {e}"));
+
+                ALTERNATE_SCREEN.store(true, Ordering::SeqCst);
+                self.origin = (0, 0);
+            },
+            ScreenMode::Inline(_) => self.reserve_rows(),
+        }
+
+        // Key releases and repeats are reported only with the enhancement flags, and
+        // keys, which produce text, only if all keys are reported as escape codes.
+        // The main and the alternate screen keep separate flag stacks, so the flags
+        // are pushed after the screen is entered
+        if self.capabilities.keyboard_enhancement {
+            crossterm::execute!(
+                self.sink,
+                crossterm::event::PushKeyboardEnhancementFlags(
+                    ctevent::KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | ctevent::KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                        | ctevent::KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                        | ctevent::KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS,
+                ),
+            )
+            .unwrap_or_else(|e| fatal!("Failed to enable keyboard enhancement: {e}"));
+
+            KEYBOARD_ENHANCEMENT.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Scrolls the screen, so the rows below the cursor fit in it, and
+    /// remembers the first of them. Rows start on a new line, if the
+    /// cursor is not at the start of one
+    fn reserve_rows(&mut self) {
+        let rows = self.screen.1.saturating_sub(1);
+        let mut out = match crossterm::cursor::position() {
+            Ok((0, _)) => vec![],
+            _ => b"\r\n".to_vec(),
+        };
+
+        out.extend(std::iter::repeat_n(b'\n', rows as usize));
+
+        if rows > 0 {
+            crossterm::queue!(out, crossterm::cursor::MoveUp(rows))
+                .unwrap_or_else(|e| fatal!("Failed to move cursor: {e}"));
+        }
+
+        crossterm::queue!(out, crossterm::cursor::Hide)
+            .and_then(|_| self.sink.write_all(&out))
+            .and_then(|_| self.sink.flush())
+            .unwrap_or_else(|e| fatal!("Failed to reserve rows: {e}"));
+
+        // Without a reply, the rows are assumed to be scrolled to the bottom of the screen
+        let row = crossterm::cursor::position()
+            .map(|(_, row)| row)
+            .or_else(|_| crossterm::terminal::size().map(|(_, rows)| rows.saturating_sub(self.screen.1)))
+            .unwrap_or(0);
+
+        let recorded = self.recorded_size();
+        self.origin = (0, row);
+        self.record_resize(recorded);
+    }
+
+    /// Disables raw mode and leaves the alternate screen. In inline
+    /// mode, the cursor is moved below the last frame instead.
+    /// Does nothing if the sink is not a TTY
+    pub fn disable(&mut self) {
+        if !self.is_tty {
+            self.sink.flush().unwrap_or_else(|e| fatal!("Failed to flush output: {e}"));
+            return;
+        }
+
+        crossterm::terminal::disable_raw_mode()
+            .unwrap_or_else(|e| fatal!("Failed to disable raw mode: {e}"));
+
+        // In inline mode the last frame stays in the scrollback
+        if self.screen_mode == ScreenMode::AlternateScreen {
+            self.finish_encoder();
+        }
+
+        // Flags are popped from the stack of the screen, they were pushed on
+        if KEYBOARD_ENHANCEMENT.swap(false, Ordering::SeqCst) {
+            crossterm::execute!(self.sink, crossterm::event::PopKeyboardEnhancementFlags)
+                .unwrap_or_else(|e| fatal!("Failed to disable keyboard enhancement: {e}"));
+        }
+
+        if MOUSE_CAPTURE.swap(false, Ordering::SeqCst) {
+            crossterm::execute!(self.sink, crossterm::event::DisableMouseCapture)
+                .unwrap_or_else(|e| fatal!("Failed to disable mouse capture: {e}"));
+        }
+
+        match self.screen_mode {
+            ScreenMode::AlternateScreen => crossterm::execute!(
+                self.sink,
+                crossterm::terminal::LeaveAlternateScreen,
+                crossterm::cursor::Show,
+            ),
+            ScreenMode::Inline(_) => crossterm::execute!(
+                self.sink,
+                crossterm::cursor::MoveTo(0, self.origin.1 + self.screen.1 - 1),
+                crossterm::style::Print("\r\n"),
+                crossterm::cursor::Show,
+            ),
+        }
+        .unwrap_or_else(|e| fatal!("Failed to restore terminal: {e}"));
+
+        ALTERNATE_SCREEN.store(false, Ordering::SeqCst);
+        TTY_ENABLED.store(false, Ordering::SeqCst);
+        RESTORE_OUT.lock().unwrap_or_else(PoisonError::into_inner).take();
+    }
+
+    /// Size of the area, in which frames are drawn: the viewport,
+    /// if there is one, or the whole screen otherwise. In the Sixel
+    /// mode the area ends above the last row of the screen
+    pub fn size(&self) -> Size {
+        Size::Terminal(self.cols, self.rows)
+    }
+
+    /// Size of the whole screen or of the reserved rows in inline mode
+    pub fn screen_size(&self) -> Size {
+        Size::Terminal(self.screen.0, self.screen.1)
+    }
+
+    /// Clears the drawn area. The whole screen is cleared only in
+    /// the alternate screen mode without a viewport
+    pub fn clear(&mut self) {
+        self.previous = None;
+
+        let mut out = vec![];
+        let (x, y) = self.offset();
+
+        match (self.screen_mode, self.viewport) {
+            (ScreenMode::AlternateScreen, None) => crossterm::queue!(out, Clear(ClearType::All), MoveTo(0, 0)),
+            // Erase Character (ECH) clears cells to the right of the cursor without wrapping
+            _ => (0..self.rows)
+                .try_for_each(|row| crossterm::queue!(out, MoveTo(x, y + row)).and_then(|_| write!(out, "\x1b[{}X", self.cols)))
+                .and_then(|_| crossterm::queue!(out, MoveTo(x, y))),
+        }
+        .and_then(|_| self.sink.write_all(&out))
+        .and_then(|_| self.sink.flush())
+        .unwrap_or_else(|e| fatal!("Failed to clear terminal: {e}"));
+
+        record(&mut self.recorder, |recorder| recorder.output(&out));
+    }
+
+    /// Draws RGBA image in the terminal using the active output mode
+    pub fn print_image(&mut self, buf: &[u8], width: u32, height: u32) {
+        let frame = Frame::from_raw(width, height, buf).unwrap();
+        let (x, y) = self.offset();
+
+        self.begin_frame();
+
+        match self.encoder.encode(&frame) {
+            Ok(Encoded::Bytes(bytes)) => {
+                self.frame.queue(MoveTo(x, y)).unwrap_or_else(|e| fatal!("Failed to move cursor: {e}"));
+                self.frame.extend_from_slice(bytes);
+                self.previous = None;
+            },
+            Ok(Encoded::Cells(grid)) => {
+                self.cells.clone_from(grid);
+                self.write_cells().unwrap_or_else(|e| fatal!("Failed to write cells: {e}"));
+            },
+            Err(e) => fatal!("Failed to encode image: {e}"),
+        }
+
+        self.end_frame();
+    }
+
+    /// Prints cells, which are already encoded, e.g. by [`CellPass`](crate::render::cells::CellPass)
+    /// on the GPU. Cell colors are quantized like the ones of [`Terminal::print_image`]
+    pub fn print_cells(&mut self, grid: &CellGrid) {
+        self.begin_frame();
+
+        self.cells.clone_from(grid);
+        self.write_cells().unwrap_or_else(|e| fatal!("Failed to write cells: {e}"));
+
+        self.end_frame();
+    }
+
+    fn begin_frame(&mut self) {
+        self.frame.clear();
+
+        if self.synchronized_output {
+            self.frame.extend_from_slice(BEGIN_SYNC_UPDATE);
+        }
+    }
+
+    /// Writes the frame to the sink and to the recorder
+    fn end_frame(&mut self) {
+        if self.synchronized_output {
+            self.frame.extend_from_slice(END_SYNC_UPDATE);
+        }
+
+        self.sink.write_all(&self.frame).unwrap_or_else(|e| fatal!("Failed to print image: {e}"));
+        self.sink.flush().unwrap_or_else(|e| fatal!("Failed to flush output: {e}"));
+
+        record(&mut self.recorder, |recorder| recorder.output(&self.frame));
+    }
+
+    /// Prints text at the screen position. With a viewport, the position is
+    /// not limited to it, so text can be printed around the drawn area
+    pub fn print_at(&mut self, text: &str, x: u16, y: u16) {
+        let (area_x, area_y) = self.offset();
+        let (area_x, area_y) = (area_x - self.origin.0, area_y - self.origin.1);
+        let width = text.chars().count().min(u16::MAX as usize) as u16;
+
+        // Text covers cells of the previous frame
+        if (area_y..area_y + self.rows).contains(&y)
+            && x < area_x + self.cols
+            && x.saturating_add(width) > area_x
+        {
+            self.previous = None;
+        }
+
+        if x < self.screen.0 && y < self.screen.1 {
+            let mut out = vec![];
+
+            crossterm::queue!(
+                out,
+                crossterm::cursor::MoveTo(self.origin.0 + x, self.origin.1 + y),
+                crossterm::style::Print(text)
+            )
+            .and_then(|_| self.sink.write_all(&out))
+            .and_then(|_| self.sink.flush())
+            .unwrap_or_else(|e| fatal!("Failed to print at position ({x}, {y}): {e}"));
+
+            record(&mut self.recorder, |recorder| recorder.output(&out));
+        } else {
+            fatal!(
+                "Position out of bounds: ({}, {}) for terminal size ({}, {})",
+                x,
+                y,
+                self.screen.0,
+                self.screen.1
+            );
+        }
+    }
+
+    /// Returns the new screen size, if the TTY has been resized.
+    /// Sinks, which are not a TTY, are never resized
+    pub fn resized(&self) -> Option<Size> {
+        if !self.is_tty {
+            return None;
+        }
+
+        crossterm::terminal::size()
+            .ok()
+            .map(|(cols, rows)| match self.screen_mode {
+                ScreenMode::AlternateScreen => (cols, rows),
+                ScreenMode::Inline(_) => (cols, self.screen.1),
+            })
+            .filter(|&size| size != self.screen)
+            .map(|(cols, rows)| Size::new_terminal(cols, rows))
+    }
+
+    /// Reads a key or mouse event, waiting for it no longer than `timeout`.
+    /// Other events are skipped
+    pub fn input(&mut self, timeout: Duration) -> Option<Input> {
+        if !ctevent::poll(timeout).ok()? {
+            return None;
+        }
+
+        match ctevent::read().ok()? {
+            ctevent::Event::Key(event) => {
+                let input = KeyboardInput::from(event);
+                self.record_input(&input);
+
+                Some(Input::Keyboard(input))
+            },
+            ctevent::Event::Mouse(event) => Some(Input::Mouse(self.mouse_input(event))),
+            _ => None,
+        }
+    }
+
+    /// Drawn area, which mouse events are mapped to. It changes
+    /// with the screen size, the viewport and the output mode
+    pub(crate) fn input_area(&self) -> InputArea {
+        InputArea {
+            offset: self.offset(),
+            resolution: self.encoder.resolution(),
+        }
+    }
+
+    fn mouse_input(&self, event: ctevent::MouseEvent) -> MouseInput {
+        self.input_area().mouse_input(event)
+    }
+
+    pub fn mouse_capture(&self) -> bool {
+        self.mouse_capture
+    }
+
+    /// Enables reporting of mouse events by [`Terminal::input`]. It is
+    /// enabled by default and takes effect in [`Terminal::enable`]. While
+    /// the mouse is captured, the terminal doesn't select text with it
+    pub fn set_mouse_capture(&mut self, enabled: bool) {
+        self.mouse_capture = enabled;
+    }
+
+    /// Resizes the terminal with the current terminal size.
+    /// Does nothing if the sink is not a TTY
+    ///
+    /// # Panics
+    /// If the terminal size cannot be retrieved, this function will panic.
+    pub fn resize_with_current(&mut self) {
+        if !self.is_tty {
+            return;
+        }
+
+        let (cols, rows) = crossterm::terminal::size()
+            .unwrap_or_else(|e| fatal!("Failed to get terminal size: {}", e));
+
+        let rows = match self.screen_mode {
+            ScreenMode::AlternateScreen => rows,
+            ScreenMode::Inline(_) => self.screen.1,
+        };
+
+        self.resize_with(Size::new_terminal(cols, rows));
+    }
+
+    /// Resizes the screen to the specified size. The viewport
+    /// is clamped to the new screen size
+    pub fn resize_with(&mut self, size: Size) {
+        let Size::Terminal(cols, rows) = size.to_terminal(self.cell_resolution()) else { unreachable!("Expected terminal size") };
+
+        let recorded = self.recorded_size();
+        self.screen = (cols, rows);
+        self.record_resize(recorded);
+
+        self.update_area();
+    }
+}
+
+/// Screen position and cell resolution of the drawn area, so mouse
+/// events can be mapped without access to the terminal
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct InputArea {
+    offset: (u16, u16),
+    resolution: CellResolution,
+}
+
+impl InputArea {
+    /// Maps the screen position of the mouse event to the drawn area
+    /// and to renderer pixels
+    pub(crate) fn mouse_input(&self, event: ctevent::MouseEvent) -> MouseInput {
+        let (x, y) = self.offset;
+        let cell = (event.column as i32 - x as i32, event.row as i32 - y as i32);
+
+        MouseInput {
+            kind: event.kind,
+            modifiers: event.modifiers,
+            cell,
+            pixel: (
+                (cell.0 as f32 + 0.5) * self.resolution.width as f32,
+                (cell.1 as f32 + 0.5) * self.resolution.height as f32,
+            ),
+        }
+    }
+}
+
+/// Passes an event to the recorder. Recording stops on the first error,
+/// so a failing recording never breaks the output itself
+fn record(recorder: &mut Option<AsciicastRecorder>, event: impl FnOnce(&mut AsciicastRecorder) -> io::Result<()>) {
+    if let Some(active) = recorder
+        && let Err(e) = event(active)
+    {
+        log::error!("Failed to record terminal output, recording is stopped: {e}");
+        *recorder = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cell::Cell;
+
+    fn print(terminal: &mut Terminal<Vec<u8>>, changed: &[u16]) -> String {
+        let mut grid = CellGrid::new(10, 1);
+        for &col in changed {
+            grid.set(col, 0, Cell { glyph: 'x', ..Cell::default() });
+        }
+
+        terminal.print_cells(&grid);
+
+        String::from_utf8(std::mem::take(terminal.sink_mut())).unwrap()
+    }
+
+    #[test]
+    fn diff_falls_back_to_full_frame() {
+        let mut terminal = Terminal::new_with_sink(10, 1, vec![], OutputMode::HalfBlock);
+
+        assert_eq!(print(&mut terminal, &[]), "\x1b[1;1H\x1b[49m          \x1b[0m");
+        assert_eq!(print(&mut terminal, &[0]), "\x1b[1;1H\x1b[39;49mx\x1b[0m");
+
+        // Two separate runs take more bytes than the whole row
+        assert_eq!(print(&mut terminal, &[0, 4, 8]), "\x1b[1;1H\x1b[39;49mx   x   x \x1b[0m");
+    }
+}
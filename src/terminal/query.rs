@@ -0,0 +1,222 @@
+//! Query module sends requests to the terminal and parses its replies
+
+use std::{
+    io::{self, IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+/// Primary Device Attributes request. Every terminal answers it,
+/// so it goes after other queries to detect the end of replies
+const DA1_REQUEST: &str = "\x1b[c";
+
+/// Escape sequence, received from the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reply<'a> {
+    /// Control sequence: parameters with intermediates and the final byte
+    Csi(&'a [u8], u8),
+    /// Operating system command without the terminator
+    Osc(&'a [u8]),
+    /// Application program command without the terminator
+    Apc(&'a [u8]),
+}
+
+/// Sends requests followed by the DA1 request to `out`, which must be
+/// a terminal, and returns received bytes, once the DA1 reply arrives
+/// or the timeout expires. Nothing is sent, if replies can't be read
+pub(crate) fn query(out: &mut impl Write, requests: &str, timeout: Duration) -> Vec<u8> {
+    let Some(input) = ReplyInput::open() else { return vec![] };
+
+    let raw_mode = crossterm::terminal::is_raw_mode_enabled().unwrap_or(false);
+    if !raw_mode && crossterm::terminal::enable_raw_mode().is_err() {
+        return vec![];
+    }
+
+    let mut received = vec![];
+
+    if out
+        .write_all(requests.as_bytes())
+        .and_then(|_| out.write_all(DA1_REQUEST.as_bytes()))
+        .and_then(|_| out.flush())
+        .is_ok()
+    {
+        let deadline = Instant::now() + timeout;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if !read_available(&input, &mut received, remaining) {
+                break;
+            }
+
+            let da1_received = replies(&received)
+                .any(|reply| matches!(reply, Reply::Csi(params, b'c') if params.starts_with(b"?")));
+
+            if da1_received {
+                break;
+            }
+        }
+    }
+
+    if !raw_mode {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+
+    received
+}
+
+/// Terminal input, which replies are read from: the standard input, if it
+/// is a terminal, or the controlling terminal, which raw mode is enabled on
+#[cfg(unix)]
+struct ReplyInput(Option<std::fs::File>);
+
+#[cfg(unix)]
+impl ReplyInput {
+    fn open() -> Option<ReplyInput> {
+        if io::stdin().is_terminal() {
+            return Some(ReplyInput(None));
+        }
+
+        std::fs::File::open("/dev/tty").ok().map(|tty| ReplyInput(Some(tty)))
+    }
+
+    fn fd(&self) -> std::os::fd::RawFd {
+        use std::os::fd::AsRawFd;
+
+        self.0.as_ref().map_or(libc::STDIN_FILENO, |tty| tty.as_raw_fd())
+    }
+}
+
+/// Replies can't be read without blocking on other platforms
+#[cfg(not(unix))]
+struct ReplyInput;
+
+#[cfg(not(unix))]
+impl ReplyInput {
+    fn open() -> Option<ReplyInput> {
+        None
+    }
+}
+
+/// Waits until the input has data and appends it to the buffer.
+/// Returns `false` if nothing has been read within the timeout
+#[cfg(unix)]
+fn read_available(input: &ReplyInput, buffer: &mut Vec<u8>, timeout: Duration) -> bool {
+    let mut fd = libc::pollfd {
+        fd: input.fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+
+    // SAFETY: `fd` is a valid pollfd structure, and the count matches
+    if unsafe { libc::poll(&mut fd, 1, timeout) } <= 0 {
+        return false;
+    }
+
+    // The input is read directly, so no bytes are left in
+    // the buffer of `std::io::Stdin` after the query
+    let mut chunk = [0u8; 1024];
+
+    // SAFETY: the pointer and the length describe the `chunk` array
+    let read = unsafe { libc::read(fd.fd, chunk.as_mut_ptr().cast(), chunk.len()) };
+    if read <= 0 {
+        return false;
+    }
+
+    buffer.extend_from_slice(&chunk[..read as usize]);
+    true
+}
+
+#[cfg(not(unix))]
+fn read_available(_input: &ReplyInput, _buffer: &mut Vec<u8>, _timeout: Duration) -> bool {
+    false
+}
+
+/// Iterates over escape sequences in the received bytes. Unfinished
+/// sequences and bytes outside of sequences are skipped
+pub(crate) fn replies(bytes: &[u8]) -> impl Iterator<Item = Reply<'_>> {
+    let mut position = 0;
+
+    std::iter::from_fn(move || {
+        loop {
+            let start = position + bytes.get(position..)?.iter().position(|b| *b == 0x1b)?;
+            let kind = *bytes.get(start + 1)?;
+            let body = start + 2;
+
+            match kind {
+                b'[' => {
+                    let len = bytes[body..].iter().position(|b| (0x40..=0x7e).contains(b))?;
+                    position = body + len + 1;
+
+                    return Some(Reply::Csi(&bytes[body..body + len], bytes[body + len]));
+                },
+                b']' | b'_' | b'P' => {
+                    // String terminated either by BEL or by ST (ESC \)
+                    let len = (body..bytes.len())
+                        .position(|i| bytes[i] == 0x07 || bytes[i..].starts_with(b"\x1b\\"))?;
+
+                    let terminator = if bytes[body + len] == 0x07 { 1 } else { 2 };
+                    position = body + len + terminator;
+
+                    match kind {
+                        b']' => return Some(Reply::Osc(&bytes[body..body + len])),
+                        b'_' => return Some(Reply::Apc(&bytes[body..body + len])),
+                        _ => continue,
+                    }
+                },
+                _ => position = start + 1,
+            }
+        }
+    })
+}
+
+/// Parses numeric parameters of a control sequence, separated by `;`.
+/// Empty parameters are 0, as in ECMA-48, so positions are kept
+pub(crate) fn numbers(params: &[u8]) -> Vec<u32> {
+    params
+        .split(|b| *b == b';')
+        .filter_map(|param| match param {
+            [] => Some(0),
+            _ => std::str::from_utf8(param).ok()?.parse().ok(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csi_replies() {
+        let replies = replies(b"\x1b[?62;4c\x1b[6;17;8t").collect::<Vec<_>>();
+
+        assert_eq!(replies, [Reply::Csi(b"?62;4", b'c'), Reply::Csi(b"6;17;8", b't')]);
+    }
+
+    #[test]
+    fn string_replies() {
+        let bytes = b"\x1b]11;rgb:0000/0000/0000\x07\x1b_Gi=31;OK\x1b\\\x1bP1$r0m\x1b\\\x1b]10;?\x1b\\";
+        let replies = replies(bytes).collect::<Vec<_>>();
+
+        // DCS replies are skipped
+        assert_eq!(replies, [
+            Reply::Osc(b"11;rgb:0000/0000/0000"),
+            Reply::Apc(b"Gi=31;OK"),
+            Reply::Osc(b"10;?"),
+        ]);
+    }
+
+    #[test]
+    fn bytes_outside_sequences_are_skipped() {
+        let replies = replies(b"abc\x1bx\x1b[5n\x1b[12").collect::<Vec<_>>();
+
+        // The last sequence is unfinished
+        assert_eq!(replies, [Reply::Csi(b"5", b'n')]);
+    }
+
+    #[test]
+    fn numeric_parameters() {
+        assert_eq!(numbers(b"4;600;800"), [4, 600, 800]);
+        assert_eq!(numbers(b"4;;800"), [4, 0, 800]);
+        assert_eq!(numbers(b";1;"), [0, 1, 0]);
+    }
+}
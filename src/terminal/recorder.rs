@@ -0,0 +1,192 @@
+//! Recorder module writes terminal sessions in the asciicast v2
+//! format, which is played back by asciinema
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossterm::event::{KeyCode, KeyEventKind, KeyModifiers};
+
+use crate::event::input::KeyboardInput;
+
+/// Writes output and input of the terminal to an asciicast v2 stream:
+/// a JSON header followed by one `[time, kind, data]` entry per line
+pub struct AsciicastRecorder {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl AsciicastRecorder {
+    /// Creates a `.cast` file and writes the header to it
+    pub fn create(path: impl AsRef<Path>, cols: u16, rows: u16) -> io::Result<AsciicastRecorder> {
+        let file = File::create(path)?;
+
+        AsciicastRecorder::new(BufWriter::new(file), cols, rows)
+    }
+
+    /// Creates a recorder, which writes to the `writer`, and writes the header
+    pub fn new(writer: impl Write + Send + 'static, cols: u16, rows: u16) -> io::Result<AsciicastRecorder> {
+        let mut recorder = AsciicastRecorder {
+            writer: Box::new(writer),
+            start: Instant::now(),
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        let term = std::env::var("TERM").unwrap_or_else(|_| "xterm-256color".to_string());
+
+        write!(
+            recorder.writer,
+            "{{\"version\": 2, \"width\": {cols}, \"height\": {rows}, \"timestamp\": {timestamp}, \"env\": {{\"TERM\": ",
+        )?;
+        write_json_string(&mut recorder.writer, &term)?;
+        writeln!(recorder.writer, "}}}}")?;
+
+        Ok(recorder)
+    }
+
+    /// Records bytes, written to the terminal, as an "o" entry
+    pub fn output(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.entry("o", &String::from_utf8_lossy(bytes))
+    }
+
+    /// Records a key press as an "i" entry with the bytes, which the
+    /// terminal would send for it. Releases and unknown keys are skipped
+    pub fn input(&mut self, input: &KeyboardInput) -> io::Result<()> {
+        if input.kind == KeyEventKind::Release {
+            return Ok(());
+        }
+
+        match key_data(input.code, input.modifiers) {
+            Some(data) => self.entry("i", &data),
+            None => Ok(()),
+        }
+    }
+
+    /// Records a terminal resize as an "r" entry
+    pub fn resize(&mut self, cols: u16, rows: u16) -> io::Result<()> {
+        self.entry("r", &format!("{cols}x{rows}"))
+    }
+
+    /// Flushes recorded entries
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn entry(&mut self, kind: &str, data: &str) -> io::Result<()> {
+        let time = self.start.elapsed().as_secs_f64();
+
+        write!(self.writer, "[{time:.6}, \"{kind}\", ")?;
+        write_json_string(&mut self.writer, data)?;
+        writeln!(self.writer, "]")
+    }
+}
+
+impl Drop for AsciicastRecorder {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+/// Bytes, which a terminal sends for the key in the normal cursor key mode.
+/// Control turns letters into control characters, and Alt prefixes ESC
+fn key_data(code: KeyCode, modifiers: KeyModifiers) -> Option<String> {
+    if modifiers.contains(KeyModifiers::ALT) {
+        return key_data(code, modifiers - KeyModifiers::ALT).map(|data| format!("\x1b{data}"));
+    }
+
+    let data = match code {
+        KeyCode::Char(c) if modifiers.contains(KeyModifiers::CONTROL) && c.is_ascii_alphabetic() => {
+            return Some(((c.to_ascii_lowercase() as u8 - b'a' + 1) as char).to_string());
+        },
+        KeyCode::Char(c) => return Some(c.to_string()),
+        KeyCode::Enter => "\r",
+        KeyCode::Tab => "\t",
+        KeyCode::BackTab => "\x1b[Z",
+        KeyCode::Backspace => "\x7f",
+        KeyCode::Esc => "\x1b",
+        KeyCode::Up => "\x1b[A",
+        KeyCode::Down => "\x1b[B",
+        KeyCode::Right => "\x1b[C",
+        KeyCode::Left => "\x1b[D",
+        KeyCode::Home => "\x1b[H",
+        KeyCode::End => "\x1b[F",
+        KeyCode::Insert => "\x1b[2~",
+        KeyCode::Delete => "\x1b[3~",
+        KeyCode::PageUp => "\x1b[5~",
+        KeyCode::PageDown => "\x1b[6~",
+        KeyCode::F(n @ 1..=4) => return Some(format!("\x1bO{}", (b'P' + n - 1) as char)),
+        KeyCode::F(n @ 5..=12) => {
+            let number = [15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5];
+            return Some(format!("\x1b[{number}~"));
+        },
+        _ => return None,
+    };
+
+    Some(data.to_string())
+}
+
+/// Writes the string as a quoted JSON string
+fn write_json_string(out: &mut impl Write, value: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            c if (c as u32) < 0x20 || c == '\x7f' => "",
+            _ => continue,
+        };
+
+        out.write_all(&value.as_bytes()[start..i])?;
+        if escaped.is_empty() {
+            write!(out, "\\u{:04x}", c as u32)?;
+        } else {
+            out.write_all(escaped.as_bytes())?;
+        }
+
+        start = i + c.len_utf8();
+    }
+
+    out.write_all(&value.as_bytes()[start..])?;
+    out.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(value: &str) -> String {
+        let mut out = vec![];
+        write_json_string(&mut out, value).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_escaping() {
+        assert_eq!(json("plain ▀ text"), "\"plain ▀ text\"");
+        assert_eq!(json("\"quoted\" \\ path"), "\"\\\"quoted\\\" \\\\ path\"");
+        assert_eq!(json("\r\n\t"), "\"\\r\\n\\t\"");
+        assert_eq!(json("\x1b[2J\x7f"), "\"\\u001b[2J\\u007f\"");
+    }
+
+    #[test]
+    fn key_bytes() {
+        assert_eq!(key_data(KeyCode::Char('w'), KeyModifiers::NONE).as_deref(), Some("w"));
+        assert_eq!(key_data(KeyCode::Char('C'), KeyModifiers::CONTROL).as_deref(), Some("\x03"));
+        assert_eq!(key_data(KeyCode::Char('x'), KeyModifiers::ALT).as_deref(), Some("\x1bx"));
+        assert_eq!(key_data(KeyCode::Up, KeyModifiers::NONE).as_deref(), Some("\x1b[A"));
+        assert_eq!(key_data(KeyCode::F(5), KeyModifiers::NONE).as_deref(), Some("\x1b[15~"));
+        assert_eq!(key_data(KeyCode::CapsLock, KeyModifiers::NONE), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! Theme module queries default colors and the 16-color palette of
+//! the terminal, so the output can match the theme of the user
+
+use std::{io::Write, time::Duration};
+
+use image::Rgb;
+
+use super::query::{query, replies, Reply};
+
+/// OSC 10 and 11 requests for the default foreground and background colors
+const DEFAULT_COLORS_QUERY: &str = "\x1b]10;?\x1b\\\x1b]11;?\x1b\\";
+
+/// Colors of the terminal theme. Colors, which the terminal
+/// hasn't reported, are `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TerminalTheme {
+    /// Default foreground color, reported to OSC 10
+    pub foreground: Option<Rgb<u8>>,
+    /// Default background color, reported to OSC 11
+    pub background: Option<Rgb<u8>>,
+    /// The first 16 palette colors, reported to OSC 4
+    pub palette: [Option<Rgb<u8>>; 16],
+}
+
+impl TerminalTheme {
+    /// Queries the colors with OSC 10, 11 and 4. Queries are written to `out`,
+    /// which must be a terminal, and the replies are awaited for no longer than `timeout`
+    pub fn query(out: &mut impl Write, timeout: Duration) -> TerminalTheme {
+        let received = query(out, &TerminalTheme::requests(), timeout);
+
+        TerminalTheme::from_replies(&received)
+    }
+
+    /// OSC 10, 11 and 4 queries of the colors. They can be sent in
+    /// a single batch with other queries, ended by a single DA1 request
+    pub(crate) fn requests() -> String {
+        let palette_query = (0..16)
+            .map(|i| format!("\x1b]4;{i};?\x1b\\"))
+            .collect::<String>();
+
+        [DEFAULT_COLORS_QUERY, &palette_query].concat()
+    }
+
+    /// Whether the terminal has reported any color
+    pub fn is_empty(&self) -> bool {
+        *self == TerminalTheme::default()
+    }
+
+    /// The 16-color palette, in which colors, not reported
+    /// by the terminal, are taken from `fallback`
+    pub fn palette_or(&self, fallback: &[Rgb<u8>; 16]) -> [Rgb<u8>; 16] {
+        std::array::from_fn(|i| self.palette[i].unwrap_or(fallback[i]))
+    }
+
+    /// Reads the colors from the received replies. Replies
+    /// to other queries are ignored
+    pub(crate) fn from_replies(received: &[u8]) -> TerminalTheme {
+        let mut theme = TerminalTheme::default();
+
+        for reply in replies(received) {
+            let Reply::Osc(data) = reply else { continue };
+            let mut params = data.split(|b| *b == b';');
+
+            match (params.next(), params.next(), params.next()) {
+                (Some(b"10"), Some(color), None) => theme.foreground = parse_color(color),
+                (Some(b"11"), Some(color), None) => theme.background = parse_color(color),
+                (Some(b"4"), Some(index), Some(color)) => {
+                    let index = std::str::from_utf8(index).ok().and_then(|index| index.parse::<usize>().ok());
+
+                    if let Some(slot) = index.and_then(|index| theme.palette.get_mut(index)) {
+                        *slot = parse_color(color);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        theme
+    }
+}
+
+/// Parses an X11 color specification `rgb:R/G/B` or `rgba:R/G/B/A` with
+/// 1 to 4 hex digits per channel. Alpha is ignored
+fn parse_color(spec: &[u8]) -> Option<Rgb<u8>> {
+    let spec = std::str::from_utf8(spec).ok()?;
+    let channels = spec.strip_prefix("rgb:").or_else(|| spec.strip_prefix("rgba:"))?;
+
+    let mut rgb = [0u8; 3];
+    let mut parts = channels.split('/');
+
+    for channel in &mut rgb {
+        let part = parts.next()?;
+        if part.is_empty() || part.len() > 4 {
+            return None;
+        }
+
+        let value = u32::from_str_radix(part, 16).ok()?;
+        let max = (1u32 << (4 * part.len())) - 1;
+
+        *channel = ((value * 255 + max / 2) / max) as u8;
+    }
+
+    Some(Rgb(rgb))
+}
@@ -1,9 +1,67 @@
 pub mod prelude {
-    pub use super::Size;
+    pub use super::{CellPixels, CellResolution, Size, Viewport};
 }
 
 pub mod macros;
 
+/// Number of canvas pixels covered by a single terminal cell.
+/// It depends on the output mode of the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellResolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CellResolution {
+    /// Resolution of the "▀" half-block output: one pixel per half of a cell
+    pub const HALF_BLOCK: CellResolution = CellResolution::new(1, 2);
+
+    pub const fn new(width: u32, height: u32) -> CellResolution {
+        CellResolution { width, height }
+    }
+}
+
+impl Default for CellResolution {
+    fn default() -> Self {
+        CellResolution::HALF_BLOCK
+    }
+}
+
+/// Size of a terminal cell in screen pixels. It is reported by
+/// the terminal and depends on its font
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellPixels {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CellPixels {
+    /// Cell size, assumed when the terminal doesn't report it
+    pub const DEFAULT: CellPixels = CellPixels::new(
+        crate::terminal::DEFAULT_CELL_PIXELS.0,
+        crate::terminal::DEFAULT_CELL_PIXELS.1,
+    );
+
+    pub const fn new(width: u32, height: u32) -> CellPixels {
+        CellPixels { width, height }
+    }
+
+    /// Width to height ratio of a single canvas pixel on the screen,
+    /// when a cell is covered by `resolution` canvas pixels
+    pub fn pixel_aspect(&self, resolution: CellResolution) -> f32 {
+        let width = self.width as f32 / resolution.width as f32;
+        let height = self.height as f32 / resolution.height as f32;
+
+        if width > 0.0 && height > 0.0 { width / height } else { 1.0 }
+    }
+}
+
+impl Default for CellPixels {
+    fn default() -> Self {
+        CellPixels::DEFAULT
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Size {
     Renderer(u32, u32),
@@ -19,17 +77,63 @@ impl Size {
         Size::Terminal(cols, rows)
     }
 
-    pub fn to_renderer(&self) -> Size {
+    /// Converts the size to canvas pixels, when a cell is covered by `resolution` pixels
+    pub fn to_renderer(&self, resolution: CellResolution) -> Size {
         match self {
             Size::Renderer(_, _) => *self,
-            Size::Terminal(cols, rows) => Size::Renderer(*cols as u32, *rows as u32 * 2),
+            Size::Terminal(cols, rows) => {
+                Size::Renderer(*cols as u32 * resolution.width, *rows as u32 * resolution.height)
+            },
         }
     }
 
-    pub fn to_terminal(&self) -> Size {
+    /// Converts the size to terminal cells, when a cell is covered by `resolution` pixels
+    pub fn to_terminal(&self, resolution: CellResolution) -> Size {
         match self {
-            Size::Renderer(w, h) => Size::Terminal(*w as u16, *h as u16 / 2),
+            Size::Renderer(w, h) => {
+                Size::Terminal((*w / resolution.width.max(1)) as u16, (*h / resolution.height.max(1)) as u16)
+            },
             Size::Terminal(_, _) => *self,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Rectangle of terminal cells, in which frames are drawn.
+/// Its position is counted from the top left cell of the screen
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u16,
+    pub y: u16,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Viewport {
+    pub fn new(x: u16, y: u16, cols: u16, rows: u16) -> Viewport {
+        Viewport { x, y, cols, rows }
+    }
+
+    /// Size of the viewport in terminal cells
+    pub fn size(&self) -> Size {
+        Size::Terminal(self.cols, self.rows)
+    }
+
+    /// Whether the cell is inside the viewport
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        (self.x..self.x.saturating_add(self.cols)).contains(&x)
+            && (self.y..self.y.saturating_add(self.rows)).contains(&y)
+    }
+
+    /// Shrinks the viewport, so it fits in the screen of the given size
+    pub fn clamp(&self, cols: u16, rows: u16) -> Viewport {
+        let x = self.x.min(cols.saturating_sub(1));
+        let y = self.y.min(rows.saturating_sub(1));
+
+        Viewport {
+            x,
+            y,
+            cols: self.cols.min(cols - x.min(cols)),
+            rows: self.rows.min(rows - y.min(rows)),
+        }
+    }
+}